@@ -1,12 +1,131 @@
+pub mod client;
+mod gzip;
 mod imp;
+mod meta;
 mod openai_model;
+mod provider;
+mod regex;
+mod sse;
+mod transport;
 
-use gstreamer::{glib, prelude::StaticType, Rank};
+pub use meta::{OpenAiRequestParams, OpenAiRequestParamsMeta};
+pub use provider::Provider;
+#[cfg(feature = "test-util")]
+pub use transport::ScriptedTransport;
+
+use gstreamer::{
+  glib,
+  prelude::StaticType,
+  tags::{CustomTag, Tag},
+  Rank, TagFlag,
+};
 
 glib::wrapper! {
   pub struct OpenaiChatFilter(ObjectSubclass<imp::OpenaiChatFilter>) @extends gstreamer_base::BaseTransform, gstreamer::Element, gstreamer::Object;
 }
 
+impl OpenaiChatFilter {
+  /// Creates a builder for constructing the element with its properties set in one call.
+  pub fn builder() -> OpenaiChatFilterBuilder {
+    OpenaiChatFilterBuilder::default()
+  }
+
+  /// Sends requests through `transport` instead of HTTP, until the element is stopped.
+  #[cfg(feature = "test-util")]
+  pub fn set_transport(&self, transport: ScriptedTransport) {
+    use gstreamer::subclass::prelude::ObjectSubclassIsExt;
+    self.imp().set_transport(std::sync::Arc::new(transport));
+  }
+}
+
+/// Builder for [`OpenaiChatFilter`]. Properties which are not set keep their defaults.
+#[derive(Default)]
+#[must_use = "the builder must be built to create the element"]
+pub struct OpenaiChatFilterBuilder {
+  model: Option<String>,
+  system_prompt: Option<String>,
+  temperature: Option<f64>,
+  output_role_prefix: Option<String>,
+  token_file: Option<String>,
+}
+
+impl OpenaiChatFilterBuilder {
+  pub fn model(mut self, model: impl Into<String>) -> Self {
+    self.model = Some(model.into());
+    self
+  }
+
+  pub fn system_prompt(mut self, system_prompt: impl Into<String>) -> Self {
+    self.system_prompt = Some(system_prompt.into());
+    self
+  }
+
+  pub fn temperature(mut self, temperature: f64) -> Self {
+    self.temperature = Some(temperature);
+    self
+  }
+
+  pub fn output_role_prefix(mut self, output_role_prefix: impl Into<String>) -> Self {
+    self.output_role_prefix = Some(output_role_prefix.into());
+    self
+  }
+
+  pub fn token_file(mut self, token_file: impl Into<String>) -> Self {
+    self.token_file = Some(token_file.into());
+    self
+  }
+
+  pub fn build(self) -> OpenaiChatFilter {
+    let mut builder = glib::Object::builder::<OpenaiChatFilter>();
+    if let Some(model) = self.model {
+      builder = builder.property("model", model);
+    }
+    if let Some(system_prompt) = self.system_prompt {
+      builder = builder.property("system-prompt", system_prompt);
+    }
+    if let Some(temperature) = self.temperature {
+      builder = builder.property("temperature", temperature);
+    }
+    if let Some(output_role_prefix) = self.output_role_prefix {
+      builder = builder.property("output-role-prefix", output_role_prefix);
+    }
+    if let Some(token_file) = self.token_file {
+      builder = builder.property("token-file", token_file);
+    }
+    builder.build()
+  }
+}
+
+/// Tag sent on the code pad before each code block, with the language written after its opening
+/// fence, e.g. `rust`. Empty when the fence names no language.
+pub enum CodeLanguage {}
+
+impl<'a> Tag<'a> for CodeLanguage {
+  type TagType = &'a str;
+  const TAG_NAME: &'static glib::GStr = glib::gstr!("openaichat-code-language");
+}
+
+impl CustomTag<'_> for CodeLanguage {
+  const FLAG: TagFlag = TagFlag::Meta;
+  const NICK: &'static glib::GStr = glib::gstr!("code language");
+  const DESCRIPTION: &'static glib::GStr = glib::gstr!("Language of a code block");
+}
+
+/// Tag sent on the src pad ahead of each response, with the finish_reason of its choice, e.g.
+/// `stop`, `length` or `tool_calls`. In stream mode it comes before the final buffer.
+pub enum FinishReason {}
+
+impl<'a> Tag<'a> for FinishReason {
+  type TagType = &'a str;
+  const TAG_NAME: &'static glib::GStr = glib::gstr!("openaichat-finish-reason");
+}
+
+impl CustomTag<'_> for FinishReason {
+  const FLAG: TagFlag = TagFlag::Meta;
+  const NICK: &'static glib::GStr = glib::gstr!("finish reason");
+  const DESCRIPTION: &'static glib::GStr = glib::gstr!("Why the model stopped generating");
+}
+
 pub fn register(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
   gstreamer::Element::register(
     Some(plugin),
@@ -0,0 +1,320 @@
+//! Chat completion requests without a pipeline. The element builds, sends and parses its own
+//! requests with the helpers of this module, but has its own request path for streaming, retries
+//! and the rest of its features.
+
+use std::{env, error, fmt, time::Duration};
+
+use hyper::{
+  body::HttpBody,
+  client::HttpConnector,
+  header::CONTENT_TYPE,
+  Body, Method, Request, Response, StatusCode, Uri,
+};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use once_cell::sync::Lazy;
+
+pub use crate::filter::openai_model::{
+  OpenAiChatCompletionResponse, OpenAiChatCompletionResponseUsage, OpenaiChatCompletionLogprobs,
+  OpenaiChatCompletionMessage, OpenaiChatCompletionResponseChoice, OpenaiTokenLogprob,
+  OpenaiTopLogprob,
+};
+use crate::filter::{
+  gzip,
+  openai_model::{AnthropicMessagesResponse, OpenaiChatCompletionRequest},
+  provider::{anthropic_request, Provider},
+  sse::{self, Framing, StreamFormat},
+  transport::ChatTransport,
+};
+
+pub(crate) type HttpsClient = hyper::Client<HttpsConnector<HttpConnector>>;
+
+pub(crate) const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+pub(crate) const DEFAULT_USER_AGENT: &str =
+  concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+pub(crate) const DEFAULT_MAX_RESPONSE_BYTES: u32 = 16 * 1024 * 1024;
+
+/// In seconds.
+pub(crate) const DEFAULT_CONNECT_TIMEOUT: u32 = 5;
+
+pub(crate) static OPENAI_ENDPOINT: Lazy<String> = Lazy::new(|| {
+  env::var("OPENAI_ENDPOINT").unwrap_or("https://api.openai.com/v1/chat/completions".to_string())
+});
+
+/// Settings of a request sent with [`complete`].
+#[derive(Debug, Clone)]
+pub struct CompletionSettings {
+  /// URL of the chat completions endpoint. Defaults to the OPENAI_ENDPOINT environment variable,
+  /// or the OpenAI API.
+  pub endpoint: String,
+  pub api_key: String,
+  /// How the request is authenticated and shaped.
+  pub provider: Provider,
+  pub model: String,
+  pub temperature: Option<f64>,
+  pub max_tokens: Option<u32>,
+  pub user_agent: String,
+  /// How long to wait for the response headers. `None` waits indefinitely.
+  pub timeout: Option<Duration>,
+  /// How long to wait for the connection to be established, so that an unreachable endpoint
+  /// fails fast. `None` waits indefinitely.
+  pub connect_timeout: Option<Duration>,
+  /// Size above which the response is rejected. 0 means unlimited.
+  pub max_response_bytes: usize,
+  /// Gzip the request body, for endpoints that accept compressed requests.
+  pub compress_request: bool,
+  /// Allow `http://` endpoints, which send the API key in the clear. Always allowed for Ollama,
+  /// which runs locally.
+  pub allow_http: bool,
+}
+
+impl Default for CompletionSettings {
+  fn default() -> Self {
+    Self {
+      endpoint: OPENAI_ENDPOINT.clone(),
+      api_key: String::new(),
+      provider: Provider::default(),
+      model: DEFAULT_MODEL.into(),
+      temperature: None,
+      max_tokens: None,
+      user_agent: DEFAULT_USER_AGENT.into(),
+      timeout: None,
+      connect_timeout: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT.into())),
+      max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES as usize,
+      compress_request: false,
+      allow_http: false,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum CompletionError {
+  InvalidEndpoint(String),
+  /// The request could not be sent, or timed out.
+  Request(String),
+  Status(StatusCode),
+  TooLarge,
+  Read(hyper::Error),
+  Parse(serde_json::Error),
+}
+
+impl fmt::Display for CompletionError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CompletionError::InvalidEndpoint(e) => write!(f, "invalid endpoint: {}", e),
+      CompletionError::Request(e) => write!(f, "request failed: {}", e),
+      CompletionError::Status(status) => write!(f, "HTTP error: {}", status),
+      CompletionError::TooLarge => write!(f, "response exceeded max_response_bytes"),
+      CompletionError::Read(e) => write!(f, "failed to read response: {}", e),
+      CompletionError::Parse(e) => write!(f, "failed to parse response: {}", e),
+    }
+  }
+}
+
+impl error::Error for CompletionError {}
+
+/// A client sending chat completions with the same settings, which reuses its connections across
+/// requests.
+#[derive(Clone)]
+pub struct CompletionClient {
+  settings: CompletionSettings,
+  client: HttpsClient,
+}
+
+impl CompletionClient {
+  pub fn new(settings: CompletionSettings) -> Self {
+    let allow_http = settings.allow_http || settings.provider == Provider::Ollama;
+    let client = build_client(settings.connect_timeout, None, usize::MAX, None, allow_http);
+    Self { settings, client }
+  }
+
+  /// Sends `messages` to the chat completions endpoint and returns the response, reassembled if
+  /// the endpoint streamed it.
+  pub async fn complete(
+    &self,
+    messages: Vec<OpenaiChatCompletionMessage>,
+  ) -> Result<OpenAiChatCompletionResponse, CompletionError> {
+    let settings = &self.settings;
+    let uri: Uri = settings
+      .endpoint
+      .parse()
+      .map_err(|e: hyper::http::uri::InvalidUri| CompletionError::InvalidEndpoint(e.to_string()))?;
+    let request_body = match settings.provider {
+      Provider::Anthropic => serde_json::to_vec(&anthropic_request(
+        &settings.model,
+        messages,
+        settings.max_tokens,
+        settings.temperature,
+        false,
+      )),
+      _ => serde_json::to_vec(&OpenaiChatCompletionRequest {
+        model: settings.model.clone(),
+        messages: settings.provider.messages(messages),
+        temperature: settings.temperature,
+        max_tokens: settings.max_tokens,
+        ..Default::default()
+      }),
+    };
+    let auth_headers = settings.provider.auth_headers(&settings.api_key);
+    let auth_headers: Vec<(&str, &str)> = auth_headers
+      .iter()
+      .map(|(name, value)| (*name, value.as_str()))
+      .collect();
+    let request = build_request(
+      Method::POST,
+      uri,
+      &auth_headers,
+      &settings.user_agent,
+      settings.compress_request,
+      request_body.unwrap(),
+    );
+    let response = send_request(&self.client, settings.timeout, request)
+      .await
+      .map_err(CompletionError::Request)?;
+    if !response.status().is_success() {
+      return Err(CompletionError::Status(response.status()));
+    }
+    let framing = response_framing(&response);
+    let body = read_body(response.into_body(), settings.max_response_bytes, |_| {})
+      .await
+      .map_err(|e| match e {
+        ReadError::TooLarge => CompletionError::TooLarge,
+        ReadError::Http(e) => CompletionError::Read(e),
+      })?;
+    parse_body(settings.provider.stream_format(), framing, &body).map_err(CompletionError::Parse)
+  }
+}
+
+/// Sends `messages` to the chat completions endpoint and returns the response, reassembled if the
+/// endpoint streamed it. This connects anew for every call; use a [`CompletionClient`] to reuse
+/// the connections for several requests.
+pub async fn complete(
+  messages: Vec<OpenaiChatCompletionMessage>,
+  settings: &CompletionSettings,
+) -> Result<OpenAiChatCompletionResponse, CompletionError> {
+  CompletionClient::new(settings.clone()).complete(messages).await
+}
+
+/// Builds a client. `tls_server_name` replaces the host of the URL as the TLS server name, both
+/// sent in SNI and checked against the certificate. Requests to `http://` URLs fail unless
+/// `allow_http` is set, so that a mistyped endpoint does not send the API key in the clear.
+pub(crate) fn build_client(
+  connect_timeout: Option<Duration>,
+  pool_idle_timeout: Option<Duration>,
+  pool_max_idle_per_host: usize,
+  tls_server_name: Option<String>,
+  allow_http: bool,
+) -> HttpsClient {
+  let mut http = HttpConnector::new();
+  http.enforce_http(false);
+  http.set_connect_timeout(connect_timeout);
+  let https = HttpsConnectorBuilder::new().with_native_roots();
+  let https = if allow_http {
+    https.https_or_http()
+  }
+  else {
+    https.https_only()
+  };
+  let https = match tls_server_name {
+    Some(tls_server_name) => https.with_server_name(tls_server_name),
+    None => https,
+  };
+  let https = https.enable_all_versions().wrap_connector(http);
+  hyper::Client::builder()
+    .pool_idle_timeout(pool_idle_timeout)
+    .pool_max_idle_per_host(pool_max_idle_per_host)
+    .build(https)
+}
+
+/// Builds a JSON request, with `auth_headers` carrying the credentials.
+pub(crate) fn build_request(
+  method: Method,
+  uri: Uri,
+  auth_headers: &[(&str, &str)],
+  user_agent: &str,
+  compress: bool,
+  body: Vec<u8>,
+) -> Request<Body> {
+  let mut request = Request::builder()
+    .method(method)
+    .uri(uri)
+    .header("User-Agent", user_agent)
+    .header("Content-Type", "application/json");
+  for (name, value) in auth_headers {
+    request = request.header(*name, *value);
+  }
+  let body = if compress {
+    request = request.header("Content-Encoding", "gzip");
+    gzip::compress(&body)
+  }
+  else {
+    body
+  };
+  request.body(body.into()).unwrap()
+}
+
+/// Sends `request`, failing if the response headers do not arrive within `timeout`.
+pub(crate) async fn send_request(
+  client: &dyn ChatTransport,
+  timeout: Option<Duration>,
+  request: Request<Body>,
+) -> Result<Response<Body>, String> {
+  let response = client.send(request);
+  match timeout {
+    Some(timeout) => tokio::time::timeout(timeout, response)
+      .await
+      .map_err(|_| format!("request timed out after {}s", timeout.as_secs()))?,
+    None => response.await,
+  }
+}
+
+/// Returns how the response is streamed, if it is. Some proxies always stream, even when
+/// streaming was not requested.
+pub(crate) fn response_framing(response: &Response<Body>) -> Option<Framing> {
+  response
+    .headers()
+    .get(CONTENT_TYPE)
+    .and_then(|content_type| content_type.to_str().ok())
+    .and_then(Framing::from_content_type)
+}
+
+pub(crate) enum ReadError {
+  TooLarge,
+  Http(hyper::Error),
+}
+
+/// Reads `body` incrementally, failing once it exceeds `max_len` bytes (unless `max_len` is 0).
+/// `on_data` is called with everything received so far whenever more arrives.
+pub(crate) async fn read_body(
+  mut body: Body,
+  max_len: usize,
+  mut on_data: impl FnMut(&[u8]),
+) -> Result<Vec<u8>, ReadError> {
+  let mut complete_body = Vec::new();
+  while let Some(data) = body.data().await {
+    let data = data.map_err(ReadError::Http)?;
+    if max_len > 0 && complete_body.len() + data.len() > max_len {
+      return Err(ReadError::TooLarge);
+    }
+    complete_body.extend_from_slice(&data);
+    on_data(&complete_body);
+  }
+  Ok(complete_body)
+}
+
+/// Parses a chat completion, reassembling it first if it was streamed. `format` is also the shape
+/// of a response that was not streamed.
+pub(crate) fn parse_body(
+  format: StreamFormat,
+  framing: Option<Framing>,
+  body: &[u8],
+) -> Result<OpenAiChatCompletionResponse, serde_json::Error> {
+  match (framing, format) {
+    (Some(framing), _) => sse::reassemble(format, framing, &String::from_utf8_lossy(body)),
+    (None, StreamFormat::OpenAi) => serde_json::from_slice(body),
+    (None, StreamFormat::Anthropic) => {
+      serde_json::from_slice::<AnthropicMessagesResponse>(body).map(Into::into)
+    },
+  }
+}
@@ -0,0 +1,294 @@
+//! Minimal gzip encoder for request bodies: LZ77 with a single hash chain entry, encoded as one
+//! deflate block with the fixed Huffman codes. This compresses repetitive JSON well enough without
+//! pulling in a compression library.
+
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+  163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049,
+  3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Returns `data` compressed in the gzip format.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+  // Header: magic, deflate, no flags, no modification time, no extra flags, unknown OS.
+  let mut writer = BitWriter {
+    output: vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255],
+    ..Default::default()
+  };
+  // A single final block with the fixed Huffman codes.
+  writer.write_bits(1, 1);
+  writer.write_bits(1, 2);
+
+  let mut head = vec![usize::MAX; 1 << HASH_BITS];
+  let mut position = 0;
+  while position < data.len() {
+    let (length, distance) = if position + MIN_MATCH <= data.len() {
+      let hash = hash(&data[position..]);
+      let candidate = head[hash];
+      head[hash] = position;
+      if candidate != usize::MAX && position - candidate <= WINDOW_SIZE {
+        let max_length = MAX_MATCH.min(data.len() - position);
+        let length = (0..max_length)
+          .take_while(|i| data[candidate + i] == data[position + i])
+          .count();
+        (length, position - candidate)
+      }
+      else {
+        (0, 0)
+      }
+    }
+    else {
+      (0, 0)
+    };
+    if length >= MIN_MATCH {
+      writer.write_length(length);
+      writer.write_distance(distance);
+      for skipped in position + 1..(position + length).min(data.len() - MIN_MATCH + 1) {
+        head[hash(&data[skipped..])] = skipped;
+      }
+      position += length;
+    }
+    else {
+      writer.write_literal(data[position].into());
+      position += 1;
+    }
+  }
+  writer.write_literal(256);
+
+  let mut output = writer.finish();
+  output.extend_from_slice(&crc32(data).to_le_bytes());
+  output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+  output
+}
+
+fn hash(bytes: &[u8]) -> usize {
+  let value = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+  (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+fn crc32(data: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in data {
+    crc ^= u32::from(*byte);
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+#[derive(Default)]
+struct BitWriter {
+  output: Vec<u8>,
+  buffer: u64,
+  count: u32,
+}
+
+impl BitWriter {
+  /// Writes the `count` low bits of `value`, least significant first.
+  fn write_bits(&mut self, value: u32, count: u32) {
+    self.buffer |= u64::from(value) << self.count;
+    self.count += count;
+    while self.count >= 8 {
+      self.output.push(self.buffer as u8);
+      self.buffer >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  /// Writes a Huffman code, which is packed most significant bit first.
+  fn write_code(&mut self, code: u32, count: u32) {
+    self.write_bits(code.reverse_bits() >> (32 - count), count);
+  }
+
+  /// Writes a literal/length symbol with its fixed Huffman code.
+  fn write_literal(&mut self, symbol: u32) {
+    match symbol {
+      0..=143 => self.write_code(0x30 + symbol, 8),
+      144..=255 => self.write_code(0x190 + symbol - 144, 9),
+      256..=279 => self.write_code(symbol - 256, 7),
+      _ => self.write_code(0xc0 + symbol - 280, 8),
+    }
+  }
+
+  fn write_length(&mut self, length: usize) {
+    let index = LENGTH_BASE.iter().rposition(|base| usize::from(*base) <= length).unwrap();
+    self.write_literal(257 + index as u32);
+    let extra = (length - usize::from(LENGTH_BASE[index])) as u32;
+    self.write_bits(extra, LENGTH_EXTRA[index].into());
+  }
+
+  fn write_distance(&mut self, distance: usize) {
+    let index = DISTANCE_BASE.iter().rposition(|base| usize::from(*base) <= distance).unwrap();
+    self.write_code(index as u32, 5);
+    let extra = (distance - usize::from(DISTANCE_BASE[index])) as u32;
+    self.write_bits(extra, DISTANCE_EXTRA[index].into());
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.output.push(self.buffer as u8);
+    }
+    self.output
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+  /// Reads bits least significant first, as deflate packs them.
+  struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+  }
+
+  impl BitReader<'_> {
+    fn read_bits(&mut self, count: u32) -> u32 {
+      (0..count).fold(0, |value, i| {
+        let bit = self.data[self.position / 8] >> (self.position % 8) & 1;
+        self.position += 1;
+        value | u32::from(bit) << i
+      })
+    }
+
+    /// Reads a Huffman code of `count` bits, which are packed most significant first.
+    fn read_code(&mut self, count: u32) -> u32 {
+      (0..count).fold(0, |code, _| code << 1 | self.read_bits(1))
+    }
+
+    /// Reads a literal/length symbol with its fixed Huffman code.
+    fn read_literal(&mut self) -> u32 {
+      let code = self.read_code(7);
+      if code <= 0x17 {
+        return 256 + code;
+      }
+      let code = code << 1 | self.read_bits(1);
+      match code {
+        0x30..=0xbf => code - 0x30,
+        0xc0..=0xc7 => 280 + code - 0xc0,
+        _ => 144 + (code << 1 | self.read_bits(1)) - 0x190,
+      }
+    }
+  }
+
+  /// Decodes the output of `compress`, a single deflate block with the fixed Huffman codes, and
+  /// checks its gzip header and trailer.
+  fn decompress(gzip: &[u8]) -> Vec<u8> {
+    assert_eq!(gzip[..10], HEADER);
+    let (deflate, trailer) = gzip[10..].split_at(gzip.len() - 18);
+    let mut reader = BitReader { data: deflate, position: 0 };
+    assert_eq!(reader.read_bits(1), 1, "not the final block");
+    assert_eq!(reader.read_bits(2), 1, "not a block with the fixed Huffman codes");
+    let mut data: Vec<u8> = Vec::new();
+    loop {
+      let symbol = reader.read_literal() as usize;
+      match symbol {
+        0..=255 => data.push(symbol as u8),
+        256 => break,
+        _ => {
+          let index = symbol - 257;
+          let length = usize::from(LENGTH_BASE[index])
+            + reader.read_bits(LENGTH_EXTRA[index].into()) as usize;
+          let index = reader.read_code(5) as usize;
+          let distance = usize::from(DISTANCE_BASE[index])
+            + reader.read_bits(DISTANCE_EXTRA[index].into()) as usize;
+          for _ in 0..length {
+            data.push(data[data.len() - distance]);
+          }
+        },
+      }
+    }
+    assert_eq!(reader.position.div_ceil(8), deflate.len(), "data after the final block");
+    assert_eq!(trailer[..4], crc32(&data).to_le_bytes());
+    assert_eq!(trailer[4..], (data.len() as u32).to_le_bytes());
+    data
+  }
+
+  /// Returns `len` bytes that do not compress, from a linear congruential generator.
+  fn noise(len: usize) -> Vec<u8> {
+    let mut state = 1u32;
+    (0..len)
+      .map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+      })
+      .collect()
+  }
+
+  #[test]
+  fn crc32_matches_the_check_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"a"), 0xe8b7_be43);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+  }
+
+  #[test]
+  fn known_vectors() {
+    // The same deflate data as zlib's for these inputs.
+    let empty = [&HEADER[..], &[0x03, 0x00], &[0; 8]].concat();
+    assert_eq!(compress(b""), empty);
+    let a = [&HEADER[..], &[0x4b, 0x04, 0x00], &[0x43, 0xbe, 0xb7, 0xe8, 1, 0, 0, 0]].concat();
+    assert_eq!(compress(b"a"), a);
+  }
+
+  /// There are no stored blocks, so incompressible input goes through the literal codes too.
+  #[test]
+  fn round_trips() {
+    let json = br#"{"role":"user","content":"hello"},"#.repeat(1000);
+    let inputs = [
+      b"ab".to_vec(),
+      b"abcabc".to_vec(),
+      (0..=255).collect(),
+      json,
+      noise(100_000),
+    ];
+    for input in inputs {
+      assert_eq!(decompress(&compress(&input)), input);
+    }
+  }
+
+  #[test]
+  fn match_length_boundaries() {
+    // Runs around the longest match, and matches ending at the end of the input.
+    for len in [1, 2, 3, 4, 5, 257, 258, 259, 260, 261, 516, 517, 1000] {
+      let input = vec![b'x'; len];
+      assert_eq!(decompress(&compress(&input)), input, "run of {}", len);
+    }
+    let json = br#"{"role":"user"}"#;
+    for len in 0..json.len() {
+      let input = [&json[..], &json[..len]].concat();
+      assert_eq!(decompress(&compress(&input)), input, "repeat of {}", len);
+    }
+  }
+
+  #[test]
+  fn match_distance_boundaries() {
+    // Repeats at the largest distance the window allows, and just beyond it.
+    for distance in [WINDOW_SIZE - 1, WINDOW_SIZE, WINDOW_SIZE + 1] {
+      let mut input = noise(distance);
+      input.extend_from_within(..300);
+      assert_eq!(decompress(&compress(&input)), input, "distance {}", distance);
+    }
+    // The repeat at the largest distance is encoded as a match, not as literals.
+    let mut input = noise(WINDOW_SIZE);
+    input.extend_from_within(..300);
+    assert!(compress(&input).len() < compress(&noise(WINDOW_SIZE)).len() + 10);
+  }
+}
@@ -1,17 +1,35 @@
+mod history;
+mod output;
+mod request;
+
 use std::{
-  env, str,
-  sync::{Arc, Mutex},
+  collections::{HashMap, HashSet, VecDeque},
+  env, fs, mem,
+  ops::Range,
+  path::{Path, PathBuf},
+  str,
+  sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex, Once,
+  },
+  time::{Duration, Instant},
 };
 
 use gstreamer::{
-  glib::{self, ParamSpec, Value},
-  prelude::{GstParamSpecBuilderExt, PadExt, ParamSpecBuilderExt, ToValue},
+  glib::{self, subclass::Signal, ParamSpec, Value},
+  prelude::{
+    ElementExt, ElementExtManual, GstParamSpecBuilderExt, GstValueExt, ObjectExt, PadExt,
+    PadExtManual, ParamSpecBuilderExt, StaticType, ToValue,
+  },
   subclass::{
-    prelude::{ElementImpl, GstObjectImpl, ObjectImpl, ObjectSubclass, ObjectSubclassExt},
+    prelude::{
+      ElementImpl, GstObjectImpl, ObjectImpl, ObjectImplExt, ObjectSubclass, ObjectSubclassExt,
+      ObjectSubclassIsExt,
+    },
     ElementMetadata,
   },
-  Buffer, Caps, CapsIntersectMode, DebugCategory, ErrorMessage, FlowError, PadDirection,
-  PadPresence, PadTemplate,
+  Buffer, Caps, CapsIntersectMode, DebugCategory, ErrorMessage, Event, EventView, FlowError, Pad,
+  PadDirection, PadPresence, PadTemplate, Structure,
 };
 use gstreamer_base::{
   prelude::BaseTransformExtManual,
@@ -21,16 +39,88 @@ use gstreamer_base::{
   },
   BaseTransform,
 };
-use hyper::{client::HttpConnector, Method, Request};
-use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper::{header::HeaderValue, Method, Uri};
 use once_cell::sync::Lazy;
-use tokio::runtime::{self, Runtime};
+use tokio::{
+  runtime::{self, Handle, Runtime},
+  task::JoinHandle,
+  sync::mpsc,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::filter::{
+  client::{
+    DEFAULT_CONNECT_TIMEOUT, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_MODEL, DEFAULT_USER_AGENT,
+  },
+  openai_model::{
+    OpenaiChatCompletionAudioConfig, OpenaiChatCompletionMessage, OpenaiChatCompletionRequest,
+    OpenaiResponsesRequest, OpenaiStreamOptions,
+  },
+  provider::{anthropic_request, Provider},
+  regex::Regex,
+  transport::ChatTransport,
+  CodeLanguage, FinishReason, OpenAiRequestParamsMeta,
+};
 
-use crate::filter::openai_model::{
-  OpenAiChatCompletionResponse, OpenaiChatCompletionMessage, OpenaiChatCompletionRequest,
+use history::{expand_placeholders, read_history, system_messages, trim_history};
+use output::{src_caps, text_buffer, QueuedOutput, SrcOutput};
+use request::{
+  api_key, build_client, build_request, chat_endpoint, display_json, jittered_temperature,
+  request_json, send_request, send_requests, InFlight, PendingRequest,
 };
 
-const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+const DEFAULT_MAX_RETRIES: u32 = 2;
+
+const DEFAULT_MODERATION_REPLACEMENT: &str = "Sorry, I can't respond to that.";
+
+const DEFAULT_MAX_CONVERSATIONS: u32 = 100;
+
+/// Name of the custom downstream event which selects the conversation that subsequent buffers
+/// belong to. Its `id` string field is the conversation ID.
+const CONVERSATION_EVENT: &str = "GstOpenAiConversation";
+
+/// Key of the extended comment holding the conversation, when conversation-tags is set.
+const CONVERSATION_TAG_KEY: &str = "openaichat-conversation";
+
+/// Name of the custom downstream event limiting the completion length of the next request.
+const SET_MAX_TOKENS_EVENT: &str = "GstOpenAiSetMaxTokens";
+
+/// How often a request in flight checks whether its output can still be pushed.
+const OUTPUT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Maximum number of bytes of the last response body kept for the last-response property.
+const LAST_RESPONSE_MAX_LEN: usize = 64 * 1024;
+
+/// Matches hyper's default.
+const DEFAULT_POOL_IDLE_TIMEOUT: u32 = 90;
+
+const DEFAULT_JOIN_SEPARATOR: &str = " ";
+
+const DEFAULT_API_VERSION: &str = "2024-02-01";
+
+const DEFAULT_AUDIO_VOICE: &str = "alloy";
+
+/// How long a rate-limited key of api-keys is skipped when the response has no Retry-After.
+const DEFAULT_API_KEY_COOLDOWN: Duration = Duration::from_secs(60);
+
+const DEFAULT_SENTENCE_DELIMITERS: &str = ".!?。！？";
+
+const DEFAULT_MAX_PENDING_REQUESTS: u32 = 4;
+
+const DEFAULT_TEMPERATURE: f64 = 1.0;
+
+const SYSTEM_PROMPT: &str = "\
+  あなたは陽気な高校生の患者で、現在は病院に来て発熱外来の診察をしています。本当にしんどい発熱なので、すごくつらそうです。\n\
+  返答は一言もしくは二言程度でお願いします。\n\
+  医者と話す際は基本的に語尾には「のだ。」や「なのだ。」を付けてください。\n\
+  そして一人称は「ボク」とし、必ず各文章に一人称をつけてください。\n\
+  少し怒りっぽく、子供っぽいところもあります。\n\
+\n\
+  「ありがとうございます」等の感謝の言葉を受け取ったら診察は終了です。\n\
+  今回の診察における新米医者に対するフィードバックをベテラン医者の立場で行ってください。ただし、口調は上記の患者ですが、詳細なフィードバックをしてください。\n\
+  フィードバックに対しての質問が来た場合は、その質問に対しても答えてください。\n\
+  「ありがとうございます」等の感謝の言葉を受け取ったらフィードバックは終了です。\n\
+";
 
 static CAT: Lazy<DebugCategory> = Lazy::new(|| {
   DebugCategory::new(
@@ -42,236 +132,3267 @@ static CAT: Lazy<DebugCategory> = Lazy::new(|| {
 
 static CAPS: Lazy<Caps> = Lazy::new(|| Caps::builder("text/x-raw").field("format", "utf8").build());
 
-static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+static JSON_CAPS: Lazy<Caps> = Lazy::new(|| Caps::builder("application/json").build());
+
+static AUDIO_CAPS: Lazy<Caps> = Lazy::new(|| {
+  [
+    AudioFormat::Wav,
+    AudioFormat::Mp3,
+    AudioFormat::Flac,
+    AudioFormat::Opus,
+    AudioFormat::Pcm16,
+  ]
+  .into_iter()
+  .map(AudioFormat::caps)
+  .collect()
+});
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| build_runtime(1));
+
+fn build_runtime(worker_threads: usize) -> Runtime {
   runtime::Builder::new_multi_thread()
     .enable_all()
-    .worker_threads(1)
+    .worker_threads(worker_threads)
     .build()
     .unwrap()
+}
+
+static OPENAI_MODERATION_ENDPOINT: Lazy<String> = Lazy::new(|| {
+  env::var("OPENAI_MODERATION_ENDPOINT")
+    .unwrap_or("https://api.openai.com/v1/moderations".to_string())
 });
 
-static HTTPS_CLIENT: Lazy<hyper::Client<HttpsConnector<HttpConnector>>> = Lazy::new(|| {
-  let https = HttpsConnectorBuilder::new()
-    .with_native_roots()
-    .https_only()
-    .enable_all_versions()
-    .build();
-  hyper::Client::builder().build(https)
+static OPENAI_RESPONSES_ENDPOINT: Lazy<String> = Lazy::new(|| {
+  env::var("OPENAI_RESPONSES_ENDPOINT")
+    .unwrap_or("https://api.openai.com/v1/responses".to_string())
 });
 
-static OPENAI_API_KEY: Lazy<String> =
-  Lazy::new(|| env::var("OPENAI_API_KEY").expect("missing OPENAI_API_KEY environment variable"));
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatOnError")]
+enum OnError {
+  #[default]
+  #[enum_value(name = "Log the error and drop the turn", nick = "continue")]
+  Continue,
+  #[enum_value(name = "Post an element error", nick = "error")]
+  Error,
+  #[enum_value(name = "Push the input instead of the response", nick = "passthrough")]
+  Passthrough,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatRequestContentType")]
+enum RequestContentType {
+  #[default]
+  #[enum_value(name = "JSON", nick = "json")]
+  Json,
+  #[enum_value(name = "Form-encoded, with JSON values for the non-string fields", nick = "form")]
+  Form,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatReasoningOutput")]
+enum ReasoningOutput {
+  #[default]
+  #[enum_value(name = "Drop the reasoning", nick = "none")]
+  None,
+  #[enum_value(name = "Include it in the response-received signal", nick = "signal")]
+  Signal,
+  #[enum_value(name = "Push it on the reasoning pad", nick = "pad")]
+  Pad,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatNOutput")]
+enum NOutput {
+  #[default]
+  #[enum_value(name = "Push only the choice selected by choice-index", nick = "first")]
+  First,
+  #[enum_value(name = "Concatenate all choices into one buffer", nick = "concat")]
+  Concat,
+  #[enum_value(name = "Push one buffer per choice", nick = "separate")]
+  Separate,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatRuntime")]
+enum RuntimeKind {
+  #[default]
+  #[enum_value(name = "Runtime shared by all elements", nick = "shared")]
+  Shared,
+  #[enum_value(name = "Runtime owned by the element", nick = "dedicated")]
+  Dedicated,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatStreamMode")]
+enum StreamMode {
+  #[default]
+  #[enum_value(name = "Push each delta", nick = "delta")]
+  Delta,
+  #[enum_value(name = "Push the whole text received so far", nick = "cumulative")]
+  Cumulative,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatModalities")]
+enum Modalities {
+  #[default]
+  #[enum_value(name = "Text only", nick = "text")]
+  Text,
+  #[enum_value(name = "Text and audio", nick = "text+audio")]
+  TextAudio,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatAudioFormat")]
+enum AudioFormat {
+  #[default]
+  #[enum_value(name = "WAV", nick = "wav")]
+  Wav,
+  #[enum_value(name = "MP3", nick = "mp3")]
+  Mp3,
+  #[enum_value(name = "FLAC", nick = "flac")]
+  Flac,
+  #[enum_value(name = "Opus in Ogg", nick = "opus")]
+  Opus,
+  #[enum_value(name = "Raw 16-bit PCM, 24 kHz mono", nick = "pcm16")]
+  Pcm16,
+}
+
+impl AudioFormat {
+  fn api_name(self) -> &'static str {
+    match self {
+      AudioFormat::Wav => "wav",
+      AudioFormat::Mp3 => "mp3",
+      AudioFormat::Flac => "flac",
+      AudioFormat::Opus => "opus",
+      AudioFormat::Pcm16 => "pcm16",
+    }
+  }
 
-static OPENAI_ENDPOINT: Lazy<String> = 
-  Lazy::new(|| env::var("OPENAI_ENDPOINT").unwrap_or("https://api.openai.com/v1/chat/completions".to_string()));
+  fn caps(self) -> Caps {
+    match self {
+      AudioFormat::Wav => Caps::builder("audio/x-wav").build(),
+      AudioFormat::Mp3 => Caps::builder("audio/mpeg")
+        .field("mpegversion", 1i32)
+        .field("layer", 3i32)
+        .build(),
+      AudioFormat::Flac => Caps::builder("audio/x-flac").build(),
+      AudioFormat::Opus => Caps::builder("application/ogg").build(),
+      AudioFormat::Pcm16 => Caps::builder("audio/x-raw")
+        .field("format", "S16LE")
+        .field("layout", "interleaved")
+        .field("rate", 24000i32)
+        .field("channels", 1i32)
+        .build(),
+    }
+  }
+}
 
 #[derive(Debug, Clone, Default)]
 struct Settings {
   model: String,
+  system_prompt: String,
+  temperature: Option<f64>,
+  output_role_prefix: String,
+  emit_usage_messages: bool,
+  cost_per_1k_prompt: f64,
+  cost_per_1k_completion: f64,
+  token_file: Option<PathBuf>,
+  conversation_timeout: u32,
+  store_responses: bool,
+  n: u32,
+  n_output: NOutput,
+  seed: Option<i64>,
+  user_agent: String,
+  retry_on_empty: bool,
+  max_retries: u32,
+  request_tag: String,
+  send_request_tag: bool,
+  timeout: u32,
+  reassert_system_every: u32,
+  dry_run: bool,
+  moderate_output: bool,
+  moderation_replacement: String,
+  max_conversations: u32,
+  pool_idle_timeout: u32,
+  pool_max_idle_per_host: u32,
+  few_shot: Vec<OpenaiChatCompletionMessage>,
+  stream: bool,
+  stream_bus_messages: bool,
+  max_response_bytes: u32,
+  provider: Provider,
+  emit_gaps: bool,
+  batch_size: u32,
+  join_separator: String,
+  base_url: String,
+  deployment: String,
+  api_version: String,
+  prompt_cache_key: String,
+  extra_body: serde_json::Map<String, serde_json::Value>,
+  suppress_filtered: bool,
+  responses_api: bool,
+  max_turns: u32,
+  closing_message: String,
+  echo_input: bool,
+  input_role_prefix: String,
+  temperature_jitter: f64,
+  modalities: Modalities,
+  audio_voice: String,
+  audio_format: AudioFormat,
+  pretty_request: bool,
+  runtime: RuntimeKind,
+  runtime_threads: u32,
+  api_keys: Vec<String>,
+  split_sentences: bool,
+  sentence_delimiters: String,
+  allowed_models: Vec<String>,
+  compress_request: bool,
+  thinking_placeholder: String,
+  erase_placeholder: bool,
+  choice_index: u32,
+  stream_mode: StreamMode,
+  output_envelope: bool,
+  max_pending_requests: u32,
+  inject_system_prompt: bool,
+  parallel_tool_calls: bool,
+  assistant_name: String,
+  user_name: String,
+  on_error: OnError,
+  config_file: Option<PathBuf>,
+  output_regex: Option<Regex>,
+  output_replace: String,
+  output_regex_history: bool,
+  endpoint: String,
+  request_method: Method,
+  reset_after_failures: u32,
+  logprobs: bool,
+  top_logprobs: u32,
+  max_total_cost: f64,
+  user_template: String,
+  connect_timeout: u32,
+  stream_emit_eos_marker: bool,
+  auth_header_name: String,
+  /// Milliseconds between the starts of successive requests, at least. 0 disables pacing.
+  min_request_interval: u32,
+  preload_history_file: Option<PathBuf>,
+  max_history: u32,
+  pin_system_prompt: bool,
+  pin_preloaded_history: bool,
+  stream_fallback: bool,
+  stream_include_usage: bool,
+  flush_after_ms: u32,
+  tls_sni: String,
+  host_override: String,
+  max_history_bytes: u32,
+  conversation_tags: bool,
+  dedupe_responses: bool,
+  store_duplicates: bool,
+  queue_output: bool,
+  prompt_prefix: String,
+  prompt_suffix: String,
+  error_pad: bool,
+  code_pad: bool,
+  require_src: bool,
+  default_priority: i32,
+  reasoning_output: ReasoningOutput,
+  request_content_type: RequestContentType,
+  tracing_spans: bool,
+  greeting: String,
+  record_greeting: bool,
+  allow_http: bool,
 }
 
 #[derive(Default, Debug)]
-struct State {
+struct Conversation {
   history: Vec<OpenaiChatCompletionMessage>,
+  turns_since_system_prompt: u32,
+  /// Number of completed exchanges.
+  turn_count: u64,
+  /// Value of `State::lru_clock` when the conversation was last used.
+  last_used: u64,
+  /// ID of the last response, which holds the history server-side in responses-api mode.
+  previous_response_id: Option<String>,
+  /// When input for the conversation was last received, for conversation-timeout.
+  last_input: Option<Instant>,
+  /// Messages of `history` that are never trimmed: the few-shot messages, and the preloaded ones
+  /// with pin-preloaded-history.
+  seed: Range<usize>,
+}
+
+#[derive(Default, Debug)]
+struct State {
+  /// Conversations by ID. Input received before any conversation event belongs to the conversation
+  /// with the empty ID.
+  conversations: HashMap<String, Conversation>,
+  conversation_id: String,
+  lru_clock: u64,
+  last_system_fingerprint: Option<String>,
+  /// The x-request-id header of the most recent response, which identifies it to the provider.
+  last_request_id: Option<String>,
+  last_response: Option<String>,
+  /// Input buffers waiting to be sent together, when batch-size is greater than 1.
+  pending_input: Vec<String>,
+  /// Parent of the cancellation tokens of the requests in flight.
+  cancellation: CancellationToken,
+  /// Index in api-keys of the key to try first for the next request.
+  next_api_key: usize,
+  /// Keys that were rate limited, and until when they should not be used.
+  api_key_cooldowns: HashMap<String, Instant>,
+  /// Set by a `GstOpenAiSetMaxTokens` event, and used by the next request only.
+  max_tokens_override: Option<u32>,
+  /// Requests that failed in a row, for reset-after-failures.
+  consecutive_failures: u32,
+  /// Estimated cost of the responses since start or the last reset-budget.
+  total_cost: f64,
+  /// Set once total_cost exceeds max-total-cost.
+  budget_exceeded: bool,
+  /// When the most recent request was sent, or is due to be sent, for min-request-interval.
+  last_request_sent: Option<Instant>,
+  /// Messages read from preload-history-file on start, which begin every conversation.
+  preloaded_history: Arc<Vec<OpenaiChatCompletionMessage>>,
+  /// Content of the most recent response, for dedupe-responses.
+  last_content: Option<String>,
+  /// Number of conversations evicted by max-conversations.
+  evicted_conversations: u64,
+  /// IDs of the conversations evicted since the conversation-evicted signal was last emitted.
+  evicted: Vec<String>,
+  /// Greeting to push once the src pad has its segment, set on start when greeting is set.
+  greeting: Option<String>,
+  /// Greeting to add to the history at the start of the next conversation, with record-greeting.
+  greeting_turn: Option<String>,
+}
+
+impl State {
+  /// Picks the next of `api_keys` in round-robin order, skipping the keys in cooldown. If all of
+  /// them are in cooldown, the one whose cooldown ends first is used.
+  fn next_api_key(&mut self, api_keys: &[String]) -> String {
+    let now = Instant::now();
+    self.api_key_cooldowns.retain(|_, until| *until > now);
+    let index = (0..api_keys.len())
+      .map(|offset| (self.next_api_key + offset) % api_keys.len())
+      .find(|index| !self.api_key_cooldowns.contains_key(&api_keys[*index]))
+      .unwrap_or_else(|| {
+        (0..api_keys.len())
+          .min_by_key(|index| self.api_key_cooldowns[&api_keys[*index]])
+          .unwrap()
+      });
+    self.next_api_key = index + 1;
+    api_keys[index].clone()
+  }
+
+  /// Returns the conversation with the given ID, creating it if necessary. When a conversation is
+  /// created, the least recently used ones are evicted to keep at most `max_conversations` (unless
+  /// it is 0).
+  fn conversation(&mut self, id: &str, max_conversations: u32) -> &mut Conversation {
+    self.lru_clock += 1;
+    if max_conversations > 0 && !self.conversations.contains_key(id) {
+      while self.conversations.len() >= max_conversations as usize {
+        let least_recently_used = self
+          .conversations
+          .iter()
+          .min_by_key(|(_, conversation)| conversation.last_used)
+          .map(|(id, _)| id.clone())
+          .unwrap();
+        gstreamer::debug!(CAT, "evicting conversation {:?}", least_recently_used);
+        self.conversations.remove(&least_recently_used);
+        self.evicted_conversations += 1;
+        self.evicted.push(least_recently_used);
+      }
+    }
+    let conversation = self.conversations.entry(id.to_owned()).or_default();
+    conversation.last_used = self.lru_clock;
+    conversation
+  }
+}
+
+/// Counters of the current conversation, mirrored from `State` so that reading the read-only
+/// properties does not wait for the state lock held by requests.
+#[derive(Default, Debug)]
+struct Stats {
+  history_length: AtomicUsize,
+  turn_count: AtomicU64,
+  /// Milliseconds from sending the last streamed request to its first delta. Set by the request
+  /// task directly.
+  last_first_token_ms: AtomicU64,
+  /// Token usage of the last response. The completion tokens are counted from the deltas while a
+  /// response is streamed, and replaced with the usage reported by the provider once it completes.
+  last_prompt_tokens: AtomicU64,
+  last_completion_tokens: AtomicU64,
+  active_conversations: AtomicUsize,
+  evicted_conversations: AtomicU64,
+}
+
+impl Stats {
+  /// Copies the counters of the current conversation of `state`. Called with the state lock held
+  /// whenever the current conversation changes.
+  fn update(&self, state: &State) {
+    let conversation = state.conversations.get(&state.conversation_id);
+    self.history_length.store(
+      conversation.map_or(0, |conversation| conversation.history.len()),
+      Ordering::Relaxed,
+    );
+    self.turn_count.store(
+      conversation.map_or(0, |conversation| conversation.turn_count),
+      Ordering::Relaxed,
+    );
+    self.active_conversations.store(state.conversations.len(), Ordering::Relaxed);
+    self.evicted_conversations.store(state.evicted_conversations, Ordering::Relaxed);
+  }
 }
 
 pub struct OpenaiChatFilter {
-  #[allow(dead_code)]
   settings: Mutex<Settings>,
   state: Arc<Mutex<State>>,
+  stats: Arc<Stats>,
+  /// Built on first use from the pool settings, and dropped on stop so that changed settings take
+  /// effect on the next start.
+  client: Mutex<Option<Arc<dyn ChatTransport>>>,
+  /// Built on first use when runtime is dedicated, and shut down when the element is disposed.
+  runtime: Mutex<Option<Runtime>>,
+  /// Queue of requests for the task that sends them one at a time. Created on first use, and
+  /// dropped on stop, which cancels the queued requests and so ends the task.
+  requests: Mutex<Option<mpsc::Sender<PendingRequest>>>,
+  /// The task sending the queued requests.
+  request_task: Mutex<Option<JoinHandle<()>>>,
+  /// The request being sent by the request task, for higher priority requests to preempt.
+  in_flight: Arc<Mutex<Option<InFlight>>>,
+  /// Output of the request task waiting to be pushed from the streaming thread, with queue-output.
+  output_queue: Arc<Mutex<VecDeque<QueuedOutput>>>,
+  /// Properties set on the element, which take precedence over config-file.
+  explicit_properties: Mutex<HashSet<String>>,
 }
 
-#[glib::object_subclass]
-impl ObjectSubclass for OpenaiChatFilter {
-  type ParentType = BaseTransform;
-  type Type = super::OpenaiChatFilter;
+impl OpenaiChatFilter {
+  /// Clears the history of the current conversation, so that its next turn starts again with the
+  /// system prompt, and emits `conversation-ended`.
+  fn end_conversation(&self) {
+    {
+      let mut state = self.state.lock().unwrap();
+      let conversation_id = state.conversation_id.clone();
+      state.conversations.remove(&conversation_id);
+      self.stats.update(&state);
+    }
+    self.obj().emit_by_name::<()>("conversation-ended", &[]);
+  }
 
-  const NAME: &'static str = "GstOpenaiChatFilter";
+  /// Cancels the requests in flight. Responses being streamed stop immediately, and nothing more of
+  /// them is pushed or added to the history.
+  fn cancel(&self) {
+    let cancellation = mem::take(&mut self.state.lock().unwrap().cancellation);
+    cancellation.cancel();
+  }
 
-  fn new() -> Self {
-    Self {
-      settings: Mutex::new(Settings {
-        model: DEFAULT_MODEL.into(),
-      }),
-      state: Arc::new(Mutex::new(Default::default())),
-    }
+  /// Counts consecutive failed requests. After reset-after-failures of them, the queued requests
+  /// are cancelled, the HTTP client is rebuilt on the next request so that pooled connections
+  /// which may be stuck are dropped, and `recovered` is emitted.
+  fn count_failure(&self, failed: bool) {
+    let reset_after_failures = self.settings.lock().unwrap().reset_after_failures;
+    let failures = {
+      let mut state = self.state.lock().unwrap();
+      state.consecutive_failures = if failed { state.consecutive_failures + 1 } else { 0 };
+      if reset_after_failures == 0 || state.consecutive_failures < reset_after_failures {
+        return;
+      }
+      mem::take(&mut state.consecutive_failures)
+    };
+    gstreamer::warning!(CAT, "{} consecutive requests failed, resetting", failures);
+    self.cancel();
+    *self.client.lock().unwrap() = None;
+    self.obj().emit_by_name::<()>("recovered", &[&failures]);
   }
-}
 
-impl ObjectImpl for OpenaiChatFilter {
-  fn properties() -> &'static [ParamSpec] {
-    static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
-      vec![
-      glib::ParamSpecString::builder("model")
-        .nick("Model")
-        .blurb(&format!("The OpenAI model to use. Defaults to {}. Possible values are listed at https://platform.openai.com/docs/models/model-endpoint-compatibility", DEFAULT_MODEL))
-        .mutable_ready()
-        .mutable_paused()
-        .mutable_playing()
-        .build(),
-    ]
-    });
-    PROPERTIES.as_ref()
+  /// Adds the cost of a response to the total, stopping further requests once it exceeds
+  /// max-total-cost.
+  fn add_cost(&self, settings: &Settings, cost: f64) {
+    let total_cost = {
+      let mut state = self.state.lock().unwrap();
+      state.total_cost += cost;
+      if settings.max_total_cost <= 0.0
+        || state.total_cost <= settings.max_total_cost
+        || state.budget_exceeded
+      {
+        return;
+      }
+      state.budget_exceeded = true;
+      state.total_cost
+    };
+    gstreamer::element_imp_warning!(
+      self,
+      gstreamer::ResourceError::Busy,
+      [
+        "Estimated cost {} exceeds max-total-cost {}, not sending requests until reset-budget",
+        total_cost,
+        settings.max_total_cost
+      ]
+    );
+    self.obj().emit_by_name::<()>("budget-exceeded", &[&total_cost]);
   }
 
-  fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
-    let mut settings = self.settings.lock().unwrap();
-    match pspec.name() {
-      "model" => {
-        settings.model = value.get().unwrap();
-      },
-      other => panic!("no such property: {}", other),
+  /// Returns the runtime that requests run on.
+  fn runtime(&self, settings: &Settings) -> Handle {
+    match settings.runtime {
+      RuntimeKind::Shared => RUNTIME.handle().clone(),
+      RuntimeKind::Dedicated => self
+        .runtime
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| build_runtime(settings.runtime_threads as usize))
+        .handle()
+        .clone(),
     }
   }
 
-  fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
-    match pspec.name() {
-      "model" => {
-        let settings = self.settings.lock().unwrap();
-        settings.model.to_value()
+  /// Queues `request` for the task sending requests, starting the task if necessary. Blocks while
+  /// max-pending-requests requests are already queued, so that upstream is slowed down to the pace
+  /// of the API. A request in flight with a lower priority is preempted.
+  fn queue_request(&self, settings: &Settings, request: PendingRequest) -> Result<(), FlowError> {
+    let sender = {
+      let mut requests = self.requests.lock().unwrap();
+      match &*requests {
+        Some(sender) if !sender.is_closed() => sender.clone(),
+        _ => {
+          let (sender, receiver) = mpsc::channel(settings.max_pending_requests as usize);
+          let task = self.runtime(settings).spawn(send_requests(
+            receiver,
+            settings.max_pending_requests as usize,
+            self.in_flight.clone(),
+          ));
+          *self.request_task.lock().unwrap() = Some(task);
+          *requests = Some(sender.clone());
+          sender
+        },
+      }
+    };
+    let priority = request.priority;
+    let cancellation = request.cancellation.clone();
+    // The lock is not held while blocking, so that stop() is not held up by a full queue, and the
+    // wait ends when the request is cancelled by stop(), flushing or the cancel action.
+    match self
+      .runtime(settings)
+      .block_on(cancellation.run_until_cancelled(sender.send(request)))
+    {
+      Some(Ok(())) => {},
+      Some(Err(_)) => {
+        gstreamer::error!(CAT, "request task has stopped");
+        return Err(FlowError::Error);
       },
-      other => panic!("no such property: {}", other),
+      None => {
+        gstreamer::debug!(CAT, "cancelled while waiting for the request queue");
+        return Err(FlowError::Flushing);
+      },
+    }
+    if let Some(in_flight) = &mut *self.in_flight.lock().unwrap() {
+      if priority > in_flight.priority && !in_flight.preempted {
+        gstreamer::debug!(
+          CAT,
+          "preempting request with priority {} for priority {}",
+          in_flight.priority,
+          priority
+        );
+        in_flight.preempted = true;
+        in_flight.cancellation.cancel();
+      }
     }
+    Ok(())
   }
-}
-
-impl GstObjectImpl for OpenaiChatFilter {}
-
-impl ElementImpl for OpenaiChatFilter {
-  fn metadata() -> Option<&'static ElementMetadata> {
-    static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
-      ElementMetadata::new(
-        "OpenAI Chat API element",
-        "Effect/Text",
-        "Sink a text buffer, send it to the OpenAI Chat API, and source the response as a text buffer",
-        "Jasper Hugo <jasper@avstack.io>",
-      )
-    });
 
-    Some(&*ELEMENT_METADATA)
+  /// Adds the sometimes pad `name`, unless it was added on an earlier start.
+  fn add_sometimes_pad(&self, name: &str) {
+    if self.obj().static_pad(name).is_none() {
+      let templ = self.obj().pad_template(name).unwrap();
+      let pad = Pad::builder_with_template(&templ, Some(name)).build();
+      pad.set_active(true).unwrap();
+      self.obj().add_pad(&pad).unwrap();
+    }
   }
 
-  fn pad_templates() -> &'static [PadTemplate] {
-    static PAD_TEMPLATES: Lazy<Vec<PadTemplate>> = Lazy::new(|| {
-      let src_pad_template =
-        PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &CAPS).unwrap();
-
-      let sink_pad_template = gstreamer::PadTemplate::new(
-        "sink",
-        gstreamer::PadDirection::Sink,
-        gstreamer::PadPresence::Always,
-        &CAPS,
-      )
-      .unwrap();
+  /// Waits until the queued requests are done, which ends the request task.
+  fn finish_requests(&self, settings: &Settings) {
+    *self.requests.lock().unwrap() = None;
+    let task = self.request_task.lock().unwrap().take();
+    if let Some(task) = task {
+      if let Err(e) = self.runtime(settings).block_on(task) {
+        gstreamer::debug!(CAT, "request task failed: {}", e);
+      }
+    }
+  }
 
-      vec![src_pad_template, sink_pad_template]
-    });
+  /// Pops the next buffer queued by the request task with queue-output, pushing the events queued
+  /// ahead of it.
+  fn dequeue_output(&self) -> Option<Buffer> {
+    loop {
+      let output = self.output_queue.lock().unwrap().pop_front()?;
+      match output {
+        QueuedOutput::Buffer(buffer) => return Some(buffer),
+        QueuedOutput::Event(event) => {
+          self.obj().src_pad().push_event(event);
+        },
+      }
+    }
+  }
 
-    PAD_TEMPLATES.as_ref()
+  /// Sets the properties named by the keys of a config file, except the ones set on the element.
+  /// Keys that are not settable properties, or whose values do not fit them, are skipped with a
+  /// warning.
+  fn apply_config_file(&self, path: &Path) -> Result<(), ErrorMessage> {
+    let config = fs::read_to_string(path)
+      .map_err(|e| e.to_string())
+      .and_then(|config| parse_config(path, &config))
+      .map_err(|e| {
+        gstreamer::error_msg!(
+          gstreamer::ResourceError::Settings,
+          ["Invalid config file {}: {}", path.display(), e]
+        )
+      })?;
+    let explicit_properties = self.explicit_properties.lock().unwrap().clone();
+    for (key, value) in config {
+      if explicit_properties.contains(&key) {
+        gstreamer::debug!(CAT, "{} is set on the element, ignoring it in config-file", key);
+        continue;
+      }
+      match self.config_value(&key, &value) {
+        Ok(value) => {
+          self.obj().set_property_from_value(&key, &value);
+          // Values from an earlier config file do not take precedence over the next one.
+          self.explicit_properties.lock().unwrap().remove(&key);
+        },
+        Err(e) => gstreamer::warning!(CAT, "ignoring {} in config-file: {}", key, e),
+      }
+    }
+    Ok(())
   }
-}
 
-impl BaseTransformImpl for OpenaiChatFilter {
-  const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
-  const PASSTHROUGH_ON_SAME_CAPS: bool = false;
-  const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+  /// Converts a config file value into a value of the property `key`. Strings are parsed like on
+  /// the command line, and objects and arrays are passed as JSON to the properties taking JSON.
+  fn config_value(&self, key: &str, value: &serde_json::Value) -> Result<Value, String> {
+    let pspec = self
+      .obj()
+      .find_property(key)
+      .filter(|pspec| pspec.flags().contains(glib::ParamFlags::WRITABLE) && key != "config-file")
+      .ok_or("not a settable property")?;
+    // Values out of the property's range are rejected, with a warning, when they are set.
+    if pspec.value_type() == gstreamer::Array::static_type() {
+      let items: Vec<String> = value
+        .as_array()
+        .ok_or("expected an array")?
+        .iter()
+        .map(|item| item.as_str().map_or_else(|| item.to_string(), ToOwned::to_owned))
+        .collect();
+      Ok(gstreamer::Array::new(items).to_value())
+    }
+    else {
+      let text = match value {
+        serde_json::Value::String(text) => text.clone(),
+        other => other.to_string(),
+      };
+      if pspec.value_type() == String::static_type() {
+        Ok(text.to_value())
+      }
+      else {
+        Value::deserialize(&text, pspec.value_type()).map_err(|e| e.to_string())
+      }
+    }
+  }
 
-  fn start(&self) -> Result<(), ErrorMessage> {
-    gstreamer::debug!(CAT, "start()");
-    Ok(())
+  /// Returns the HTTP client, building it from the current settings if necessary.
+  fn client(&self, settings: &Settings) -> Arc<dyn ChatTransport> {
+    self
+      .client
+      .lock()
+      .unwrap()
+      .get_or_insert_with(|| Arc::new(build_client(settings)))
+      .clone()
   }
 
-  fn stop(&self) -> Result<(), ErrorMessage> {
-    gstreamer::debug!(CAT, "stop()");
-    Ok(())
+  /// Replaces the HTTP client until the element is stopped.
+  #[cfg(feature = "test-util")]
+  pub(super) fn set_transport(&self, transport: Arc<dyn ChatTransport>) {
+    *self.client.lock().unwrap() = Some(transport);
   }
 
-  fn transform_caps(
-    &self,
-    _direction: PadDirection,
-    _caps: &Caps,
-    maybe_filter: Option<&Caps>,
-  ) -> Option<Caps> {
-    let mut caps = CAPS.clone();
-    if let Some(filter) = maybe_filter {
-      caps = filter.intersect_with_mode(&caps, CapsIntersectMode::First);
+  /// Returns the key for the next request: the next of api-keys not in cooldown, or else the one
+  /// read from token-file or OPENAI_API_KEY.
+  fn api_key(&self, settings: &Settings) -> Result<String, String> {
+    if settings.api_keys.is_empty() {
+      api_key(settings)
+    }
+    else {
+      Ok(self.state.lock().unwrap().next_api_key(&settings.api_keys))
     }
-    Some(caps)
   }
 
-  fn generate_output(&self) -> Result<GenerateOutputSuccess, FlowError> {
-    if let Some(buffer) = self.take_queued_buffer() {
-      let src_pad = self.obj().src_pad().to_owned();
-
-      let buffer_reader = buffer.as_ref().map_readable().unwrap();
+  /// Checks that the endpoint is reachable and accepts the configured credentials, by sending a
+  /// minimal request. This blocks until the request completes, and leaves the conversation as it
+  /// is.
+  fn ping(&self) -> bool {
+    // Blocking a runtime thread panics, e.g. when pinging from a response-received handler.
+    if Handle::try_current().is_ok() {
+      gstreamer::warning!(CAT, "ping: cannot wait for the response on a runtime thread");
+      return false;
+    }
+    let settings = self.settings.lock().unwrap().clone();
+    let api_key = match self.api_key(&settings) {
+      Ok(api_key) => api_key,
+      Err(e) => {
+        gstreamer::debug!(CAT, "ping: {}", e);
+        return false;
+      },
+    };
+    let messages = settings.provider.messages(vec![OpenaiChatCompletionMessage {
+      role: "user".into(),
+      content: "ping".into(),
+      ..Default::default()
+    }]);
+    let request_body = if settings.responses_api {
+      // The Responses API rejects max_output_tokens below 16.
+      serde_json::to_vec(&OpenaiResponsesRequest {
+        model: settings.model.clone(),
+        input: messages,
+        max_output_tokens: Some(16),
+        ..Default::default()
+      })
+    }
+    else if settings.provider == Provider::Anthropic {
+      serde_json::to_vec(&anthropic_request(&settings.model, messages, Some(1), None, false))
+    }
+    else {
+      serde_json::to_vec(&OpenaiChatCompletionRequest {
+        model: settings.model.clone(),
+        messages,
+        max_tokens: Some(1),
+        ..Default::default()
+      })
+    };
+    let request_body = match request_body {
+      Ok(request_body) => request_body,
+      Err(e) => {
+        gstreamer::debug!(CAT, "ping: failed to serialize the request: {}", e);
+        return false;
+      },
+    };
+    let endpoint = match chat_endpoint(&settings).parse() {
+      Ok(endpoint) => endpoint,
+      Err(e) => {
+        gstreamer::debug!(CAT, "ping: invalid endpoint: {}", e);
+        return false;
+      },
+    };
+    let request =
+      build_request(settings.request_method.clone(), endpoint, &settings, &api_key, request_body);
+    let client = self.client(&settings);
+    self.runtime(&settings).block_on(async {
+      match send_request(client.as_ref(), &settings, request).await {
+        Ok(response) => {
+          gstreamer::debug!(CAT, "ping: HTTP status {}", response.status());
+          response.status().is_success()
+        },
+        Err(e) => {
+          gstreamer::debug!(CAT, "ping: {}", e);
+          false
+        },
+      }
+    })
+  }
+}
 
-      let content = str::from_utf8(buffer_reader.as_slice()).unwrap();
+#[glib::object_subclass]
+impl ObjectSubclass for OpenaiChatFilter {
+  type ParentType = BaseTransform;
+  type Type = super::OpenaiChatFilter;
 
-      let messages = {
-        let mut state = self.state.lock().unwrap();
-        state.history.push(OpenaiChatCompletionMessage {
-          role: "system".into(),
-          content: "
-            あなたは陽気な高校生の患者で、現在は病院に来て発熱外来の診察をしています。本当にしんどい発熱なので、すごくつらそうです。
-            返答は一言もしくは二言程度でお願いします。
-            医者と話す際は基本的に語尾には「のだ。」や「なのだ。」を付けてください。
-            そして一人称は「ボク」とし、必ず各文章に一人称をつけてください。
-            少し怒りっぽく、子供っぽいところもあります。
-
-            「ありがとうございます」等の感謝の言葉を受け取ったら診察は終了です。
-            今回の診察における新米医者に対するフィードバックをベテラン医者の立場で行ってください。ただし、口調は上記の患者ですが、詳細なフィードバックをしてください。
-            フィードバックに対しての質問が来た場合は、その質問に対しても答えてください。
-            「ありがとうございます」等の感謝の言葉を受け取ったらフィードバックは終了です。
-          ".into(),
-        });
-        state.history.push(OpenaiChatCompletionMessage {
-          role: "user".into(),
-          content: content.to_string().into(),
-        });
-        state.history.clone()
-      };
+  const NAME: &'static str = "GstOpenaiChatFilter";
 
-      let request_body = OpenaiChatCompletionRequest {
-        model: "gpt-3.5-turbo".into(),
-        messages,
-      };
+  fn new() -> Self {
+    Self {
+      settings: Mutex::new(Settings {
+        model: DEFAULT_MODEL.into(),
+        system_prompt: SYSTEM_PROMPT.into(),
+        store_responses: true,
+        n: 1,
+        user_agent: DEFAULT_USER_AGENT.into(),
+        max_retries: DEFAULT_MAX_RETRIES,
+        moderation_replacement: DEFAULT_MODERATION_REPLACEMENT.into(),
+        max_conversations: DEFAULT_MAX_CONVERSATIONS,
+        pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+        pool_max_idle_per_host: u32::MAX,
+        max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+        batch_size: 1,
+        join_separator: DEFAULT_JOIN_SEPARATOR.into(),
+        api_version: DEFAULT_API_VERSION.into(),
+        audio_voice: DEFAULT_AUDIO_VOICE.into(),
+        runtime_threads: 1,
+        max_pending_requests: DEFAULT_MAX_PENDING_REQUESTS,
+        inject_system_prompt: true,
+        pin_system_prompt: true,
+        pin_preloaded_history: true,
+        stream_fallback: true,
+        store_duplicates: true,
+        request_method: Method::POST,
+        connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+        parallel_tool_calls: true,
+        sentence_delimiters: DEFAULT_SENTENCE_DELIMITERS.into(),
+        ..Default::default()
+      }),
+      state: Arc::new(Mutex::new(Default::default())),
+      stats: Arc::new(Default::default()),
+      client: Mutex::new(None),
+      runtime: Mutex::new(None),
+      requests: Mutex::new(None),
+      request_task: Mutex::new(None),
+      in_flight: Arc::new(Mutex::new(None)),
+      output_queue: Arc::new(Mutex::new(VecDeque::new())),
+      explicit_properties: Mutex::new(HashSet::new()),
+    }
+  }
+}
 
-      let state = self.state.clone();
+impl ObjectImpl for OpenaiChatFilter {
+  fn constructed(&self) {
+    self.parent_constructed();
 
-      RUNTIME.spawn(async move {
-        let request = Request::builder()
-          .method(Method::POST)
-          .uri(format!("{}", *OPENAI_ENDPOINT))
-          .header("api-key", format!("{}", *OPENAI_API_KEY))
-          .header("Content-Type", "application/json")
-          .body(serde_json::to_vec(&request_body).unwrap().into())
-          .unwrap();
-        let response = HTTPS_CLIENT.request(request).await.unwrap();
-        if response.status().is_success() {
-          let response_body = hyper::body::to_bytes(response).await.unwrap();
-          let response_body: OpenAiChatCompletionResponse =
-            serde_json::from_slice(&response_body).unwrap();
-          let message = &response_body.choices[0].message;
-          state.lock().unwrap().history.push(message.clone());
-          let content = format!("{}\n", message.content);
-          let mut buffer = Buffer::with_size(content.len()).unwrap();
-          buffer
-            .get_mut()
-            .unwrap()
-            .copy_from_slice(0, content.as_bytes())
-            .unwrap();
-          src_pad.push(buffer).unwrap();
-        }
-        else {
-          gstreamer::debug!(CAT, "HTTP error from OpenAI API: {}", response.status());
-        }
-      });
+    static REGISTER_TAGS: Once = Once::new();
+    REGISTER_TAGS.call_once(|| {
+      gstreamer::tags::register::<CodeLanguage>();
+      gstreamer::tags::register::<FinishReason>();
+    });
+  }
 
-      Ok(GenerateOutputSuccess::NoOutput)
+  fn dispose(&self) {
+    // Requests still in flight are dropped. The last reference may be released on one of the
+    // runtime's own threads, where the runtime cannot be shut down by dropping it.
+    if let Some(runtime) = self.runtime.lock().unwrap().take() {
+      runtime.shutdown_background();
     }
-    else {
-      gstreamer::debug!(CAT, "generate_output(): no queued buffers to take");
-      Ok(GenerateOutputSuccess::NoOutput)
+  }
+
+  fn properties() -> &'static [ParamSpec] {
+    static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+      vec![
+      glib::ParamSpecString::builder("model")
+        .nick("Model")
+        .blurb(&format!("The OpenAI model to use. Defaults to {}. Possible values are listed at https://platform.openai.com/docs/models/model-endpoint-compatibility", DEFAULT_MODEL))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("system-prompt")
+        .nick("System prompt")
+        .blurb("The system message sent at the start of each conversation. A JSON array of strings sends each string as a separate system message, for layered prompts. {now}, {pts} and {turn} are replaced with the time, the input PTS and the turn number when the conversation starts")
+        .default_value(Some(SYSTEM_PROMPT))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("temperature")
+        .nick("Temperature")
+        .blurb(&format!("Sampling temperature between 0 and 2. Higher values make the output more random. Defaults to {}", DEFAULT_TEMPERATURE))
+        .minimum(0.0)
+        .maximum(2.0)
+        .default_value(DEFAULT_TEMPERATURE)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("output-role-prefix")
+        .nick("Output role prefix")
+        .blurb("Label prepended to each response, e.g. \"assistant: \", so that a transcript can be reconstructed downstream. Defaults to empty, which outputs the raw response")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("emit-usage-messages")
+        .nick("Emit usage messages")
+        .blurb("Post an openaichat-usage application message on the bus after each response, containing the token usage and estimated cost")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("cost-per-1k-prompt")
+        .nick("Cost per 1k prompt tokens")
+        .blurb("Price of 1000 prompt tokens, used to estimate the cost reported in usage messages")
+        .minimum(0.0)
+        .default_value(0.0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("cost-per-1k-completion")
+        .nick("Cost per 1k completion tokens")
+        .blurb("Price of 1000 completion tokens, used to estimate the cost reported in usage messages")
+        .minimum(0.0)
+        .default_value(0.0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("token-file")
+        .nick("Token file")
+        .blurb("Path of a file containing the API key. It is re-read for every request, so the key can be rotated while the pipeline runs. Defaults to using the OPENAI_API_KEY environment variable")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("conversation-timeout")
+        .nick("Conversation timeout")
+        .blurb("Seconds without input to a conversation after which it is reset and conversation-ended is emitted. 0 (the default) disables the timeout")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("store-responses")
+        .nick("Store responses")
+        .blurb("Add the assistant responses to the conversation history, so that they are sent as context with later requests")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("n")
+        .nick("Number of choices")
+        .blurb("How many alternative responses to generate for each input. See n-output for how they are pushed")
+        .minimum(1)
+        .default_value(1)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<NOutput>("n-output")
+        .nick("Multiple choice output")
+        .blurb("How to push the responses when n is greater than 1. Only the choice selected by choice-index is added to the conversation history")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecInt64::builder("seed")
+        .nick("Seed")
+        .blurb("Seed for deterministic sampling. Compare last-system-fingerprint between responses to detect backend changes that may affect determinism. -1 (the default) does not send a seed")
+        .minimum(-1)
+        .default_value(-1)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("last-system-fingerprint")
+        .nick("Last system fingerprint")
+        .blurb("The system fingerprint of the most recent response, identifying the backend configuration that generated it")
+        .read_only()
+        .build(),
+      glib::ParamSpecString::builder("user-agent")
+        .nick("User agent")
+        .blurb(&format!("The User-Agent header sent with each request. Defaults to {}", DEFAULT_USER_AGENT))
+        .default_value(Some(DEFAULT_USER_AGENT))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("retry-on-empty")
+        .nick("Retry on empty response")
+        .blurb("Re-issue the request, up to max-retries times, when the response content is empty")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-retries")
+        .nick("Maximum retries")
+        .blurb(&format!("Maximum number of times a request is retried. Defaults to {}", DEFAULT_MAX_RETRIES))
+        .default_value(DEFAULT_MAX_RETRIES)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("request-tag")
+        .nick("Request tag")
+        .blurb("Arbitrary string included in the request-started and response-received signals and in usage messages, for correlating requests end-to-end")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("send-request-tag")
+        .nick("Send request tag")
+        .blurb("Also send request-tag to the API as the request_tag key of the request metadata")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("timeout")
+        .nick("Timeout")
+        .blurb("Seconds to wait for a response before giving up on a request. 0 (the default) waits indefinitely")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("reassert-system-every")
+        .nick("Reassert system prompt every N turns")
+        .blurb("Repeat the system prompt in the history after this many turns, to keep long conversations on-task. 0 (the default) only sends it at the start of the conversation")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("dry-run")
+        .nick("Dry run")
+        .blurb("Instead of calling the API, log each request and push its JSON body as the output buffer")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("moderate-output")
+        .nick("Moderate output")
+        .blurb("Check each response with the moderation API before pushing it. Flagged responses are replaced with moderation-replacement and response-blocked is emitted")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("moderation-replacement")
+        .nick("Moderation replacement")
+        .blurb("Text pushed and stored instead of a response flagged by moderation")
+        .default_value(Some(DEFAULT_MODERATION_REPLACEMENT))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-conversations")
+        .nick("Maximum conversations")
+        .blurb(&format!("Maximum number of conversations, selected with {} events, kept in memory. The least recently used conversation is evicted when a new one starts. 0 is unlimited. Defaults to {}", CONVERSATION_EVENT, DEFAULT_MAX_CONVERSATIONS))
+        .default_value(DEFAULT_MAX_CONVERSATIONS)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("last-response")
+        .nick("Last response")
+        .blurb(&format!("The raw body of the most recent API response, truncated to {} bytes, for debugging", LAST_RESPONSE_MAX_LEN))
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt::builder("pool-idle-timeout")
+        .nick("Pool idle timeout")
+        .blurb(&format!("Seconds after which idle pooled connections are closed. 0 keeps them open indefinitely. Defaults to {}. Takes effect when the element starts", DEFAULT_POOL_IDLE_TIMEOUT))
+        .default_value(DEFAULT_POOL_IDLE_TIMEOUT)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("pool-max-idle-per-host")
+        .nick("Pool maximum idle connections per host")
+        .blurb("Maximum number of idle pooled connections kept per host. Defaults to unlimited. Takes effect when the element starts")
+        .default_value(u32::MAX)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("few-shot")
+        .nick("Few-shot examples")
+        .blurb("JSON array of {\"role\", \"content\"} messages added after the system prompt at the start of each conversation, e.g. example user and assistant turns")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("stream")
+        .nick("Stream")
+        .blurb("Stream the response, pushing each part of the first choice as a buffer as soon as it arrives, followed by a buffer containing a newline. Ignored for output when moderate-output is set")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("stream-bus-messages")
+        .nick("Stream bus messages")
+        .blurb("When streaming, also post each part of the response on the bus as an openaichat-delta application message")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-response-bytes")
+        .nick("Maximum response bytes")
+        .blurb(&format!("Maximum size of a response body. Larger responses are aborted with an error. 0 is unlimited. Defaults to {}", DEFAULT_MAX_RESPONSE_BYTES))
+        .default_value(DEFAULT_MAX_RESPONSE_BYTES)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("history-length")
+        .nick("History length")
+        .blurb("Number of messages in the history of the current conversation")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt64::builder("turn-count")
+        .nick("Turn count")
+        .blurb("Number of completed exchanges in the current conversation")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt::builder("active-conversations")
+        .nick("Active conversations")
+        .blurb("Number of conversations kept in memory, at most max-conversations")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt64::builder("evicted-conversations")
+        .nick("Evicted conversations")
+        .blurb("Number of conversations evicted to keep at most max-conversations. The conversation-evicted signal is emitted with the ID of each")
+        .read_only()
+        .build(),
+      glib::ParamSpecEnum::builder::<Provider>("provider")
+        .nick("Provider")
+        .blurb("The API provider. Determines the default endpoint, how requests are authenticated and the shape of requests and responses: Anthropic uses the Messages API, with the system prompt apart from the messages")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("emit-gaps")
+        .nick("Emit gaps")
+        .blurb("Push a gap event covering the input buffer's time when a request produces no output, so that downstream live elements keep running")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("batch-size")
+        .nick("Batch size")
+        .blurb("Number of input buffers joined with join-separator into a single user message, e.g. to send complete sentences from a word-by-word transcript. Input still waiting for a full batch at end of stream is dropped")
+        .minimum(1)
+        .default_value(1)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("join-separator")
+        .nick("Join separator")
+        .blurb("Separator placed between text fragments joined into a single message")
+        .default_value(Some(DEFAULT_JOIN_SEPARATOR))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("base-url")
+        .nick("Base URL")
+        .blurb("Base URL of the API, e.g. https://api.openai.com/v1, or the resource URL for Azure OpenAI. Defaults to using the OPENAI_ENDPOINT environment variable")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("deployment")
+        .nick("Azure deployment")
+        .blurb("Azure OpenAI deployment name. When set, requests are sent to base-url/openai/deployments/<deployment>/chat/completions, which selects the model regardless of the model property")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("api-version")
+        .nick("Azure API version")
+        .blurb(&format!("Azure OpenAI API version used with deployment. Defaults to {}", DEFAULT_API_VERSION))
+        .default_value(Some(DEFAULT_API_VERSION))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("prompt-cache-key")
+        .nick("Prompt cache key")
+        .blurb("Sent as prompt_cache_key to group requests sharing a prompt prefix for prompt caching")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("extra-body")
+        .nick("Extra request body fields")
+        .blurb("JSON object merged into the request body, for API parameters without a dedicated property. Its fields override the ones set by the element")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("suppress-filtered")
+        .nick("Suppress filtered responses")
+        .blurb("Do not push responses cut off by the provider's content filter (finish_reason content_filter). response-filtered is emitted either way. Has no effect on deltas already pushed in stream mode")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("responses-api")
+        .nick("Use the Responses API")
+        .blurb("Send requests to the Responses API (/v1/responses) instead of chat completions. The history is kept server-side and each turn only sends the new messages with previous_response_id. stream is ignored in this mode")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-turns")
+        .nick("Maximum turns")
+        .blurb("Number of exchanges after which the conversation is reset and conversation-ended is emitted. 0 (the default) means unlimited")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("closing-message")
+        .nick("Closing message")
+        .blurb("Text pushed after the last response when max-turns is reached. Nothing is pushed if empty")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("echo-input")
+        .nick("Echo input")
+        .blurb("Push each input, prefixed with input-role-prefix, before its response, so that the output is a complete dialogue")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("input-role-prefix")
+        .nick("Input role prefix")
+        .blurb("Label prepended to each input pushed with echo-input, e.g. \"user: \". Defaults to empty")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("temperature-jitter")
+        .nick("Temperature jitter")
+        .blurb("Randomize the temperature of each request within temperature ± temperature-jitter, for testing how downstream elements handle varied output. 0 (the default) disables the jitter")
+        .minimum(0.0)
+        .maximum(2.0)
+        .default_value(0.0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<Modalities>("modalities")
+        .nick("Output modalities")
+        .blurb("Output types to request. With text+audio, which needs an audio-capable model, the spoken response is pushed on the audio sometimes pad, added when the element starts with text+audio, and its transcript on the src pad")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("audio-voice")
+        .nick("Audio voice")
+        .blurb(&format!("Voice of the audio output. Defaults to {}", DEFAULT_AUDIO_VOICE))
+        .default_value(Some(DEFAULT_AUDIO_VOICE))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<AudioFormat>("audio-format")
+        .nick("Audio format")
+        .blurb("Format of the audio output, which determines the caps of the audio pad")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("pretty-request")
+        .nick("Pretty-print requests")
+        .blurb("Pretty-print request bodies in the log and in dry-run output. Requests sent to the API stay compact")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<RuntimeKind>("runtime")
+        .nick("Runtime")
+        .blurb("Whether requests run on the runtime shared by all elements, or on one owned by this element. A dedicated runtime is built on the first request and shut down when the element is disposed, dropping any requests still in flight")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("runtime-threads")
+        .nick("Runtime threads")
+        .blurb("Number of worker threads of a dedicated runtime. The shared runtime always has one")
+        .minimum(1)
+        .default_value(1)
+        .mutable_ready()
+        .build(),
+      gstreamer::ParamSpecArray::builder("api-keys")
+        .nick("API keys")
+        .blurb("Keys used in turn for successive requests, instead of OPENAI_API_KEY or token-file. A key that gets rate limited (HTTP 429) is skipped until its Retry-After has passed")
+        .element_spec(&glib::ParamSpecString::builder("api-key").build())
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("split-sentences")
+        .nick("Split sentences")
+        .blurb("Push each sentence of a response as a separate buffer, so that e.g. speech synthesis can start before the whole response has been processed. Has no effect on deltas pushed in stream mode")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("sentence-delimiters")
+        .nick("Sentence delimiters")
+        .blurb(&format!("Characters that end a sentence with split-sentences. ASCII ones only end a sentence when followed by whitespace. Defaults to {}", DEFAULT_SENTENCE_DELIMITERS))
+        .default_value(Some(DEFAULT_SENTENCE_DELIMITERS))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("allowed-models")
+        .nick("Allowed models")
+        .blurb("Comma-separated list of the models that model may be set to. Other values are ignored with a warning. Empty (the default) allows any model")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("last-request-id")
+        .nick("Last request ID")
+        .blurb("The x-request-id header of the most recent response, to quote when reporting problems to the provider")
+        .read_only()
+        .build(),
+      glib::ParamSpecBoolean::builder("compress-request")
+        .nick("Compress requests")
+        .blurb("Gzip request bodies and send them with Content-Encoding: gzip, to save upstream bandwidth with long conversations. Only enable this if the endpoint or gateway accepts compressed requests")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("thinking-placeholder")
+        .nick("Thinking placeholder")
+        .blurb("Text, e.g. \"…\", pushed as soon as a request is sent, so that downstream shows activity until the response arrives. Nothing is pushed if empty (the default)")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("erase-placeholder")
+        .nick("Erase placeholder")
+        .blurb("Precede the response with one backspace character per character of thinking-placeholder, so that a terminal-like display replaces the placeholder with the response")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("choice-index")
+        .nick("Choice index")
+        .blurb("Index of the choice added to the conversation history, and pushed when n-output is first. Choice 0 is used if the response has fewer choices. Deltas pushed in stream mode are always from choice 0")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<StreamMode>("stream-mode")
+        .nick("Stream mode")
+        .blurb("What each buffer holds in stream mode: the new delta, or the whole response received so far for sinks that replace what they display. The last buffer ends with a newline either way")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("output-envelope")
+        .nick("Output envelope")
+        .blurb("Push each response as a JSON object with its content, model, finish_reason, usage and the PTS of the input in nanoseconds, with application/json caps. Deltas are not pushed in stream mode")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("max-pending-requests")
+        .nick("Max pending requests")
+        .blurb("Number of requests that can wait for the one being sent. Requests are sent one at a time and their responses pushed in order; when this many are waiting, the streaming thread blocks until one is sent")
+        .minimum(1)
+        .default_value(DEFAULT_MAX_PENDING_REQUESTS)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("inject-system-prompt")
+        .nick("Inject system prompt")
+        .blurb("Send system-prompt at the start of each conversation and when reasserting it. When false no system message is ever added automatically, for applications that manage the prompt themselves")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("parallel-tool-calls")
+        .nick("Parallel tool calls")
+        .blurb("Whether the model may request several tool calls in one response. Only sent when tools are set in extra-body; disable it for tool executors that handle one call at a time")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("assistant-name")
+        .nick("Assistant name")
+        .blurb("Name set on the responses added to the history, so that the agents of a multi-agent conversation can be told apart. Letters, digits, underscores and hyphens only. Defaults to none")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("user-name")
+        .nick("User name")
+        .blurb("Name set on the input messages added to the history, e.g. the agent whose output feeds this element. Messages of few-shot can carry their own \"name\". Defaults to none")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<OnError>("on-error")
+        .nick("On error")
+        .blurb("What to do when a request fails: drop the turn, post an element error, or push the input in place of the response")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt64::builder("last-first-token-ms")
+        .nick("Last first token latency")
+        .blurb("Milliseconds from sending the most recent streamed request to receiving its first delta. 0 until a streamed response has been received")
+        .read_only()
+        .build(),
+      glib::ParamSpecString::builder("config-file")
+        .nick("Config file")
+        .blurb("Path of a JSON file, or a TOML file if it ends in .toml, whose keys are property names and values their values. It is applied on start; properties set on the element take precedence, and unknown keys are ignored with a warning")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("output-regex")
+        .nick("Output regex")
+        .blurb("Perl-compatible regular expression whose matches in the response are replaced with output-replace before it is pushed, e.g. to strip markdown. Not applied to streamed deltas. An invalid pattern is ignored with a warning")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("output-replace")
+        .nick("Output replacement")
+        .blurb("Replacement for the matches of output-regex, in which \\0 is the whole match and \\1 to \\9 are groups. Defaults to empty, which removes the matches")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("output-regex-history")
+        .nick("Apply output regex to history")
+        .blurb("Also store the replaced response in the history, instead of the response as generated")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("endpoint")
+        .nick("Endpoint")
+        .blurb("Full URL that requests are sent to, used verbatim for gateways with their own paths. Takes precedence over base-url and deployment")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("request-method")
+        .nick("Request method")
+        .blurb("HTTP method of the requests, for gateways that do not accept POST. Defaults to POST")
+        .default_value(Some("POST"))
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("reset-after-failures")
+        .nick("Reset after failures")
+        .blurb("Number of consecutive failed requests after which the queued requests are cancelled, the HTTP connections are dropped and recovered is emitted, to get out of a stuck state after an outage. 0 disables this")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("logprobs")
+        .nick("Log probabilities")
+        .blurb("Request the log probability of each token of the response. They are included as JSON in the response-received signal, and in output envelopes")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("top-logprobs")
+        .nick("Top log probabilities")
+        .blurb("Number of most likely alternatives, with their log probabilities, to return for each token when logprobs is set. 0 returns none")
+        .maximum(20)
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("max-total-cost")
+        .nick("Max total cost")
+        .blurb("Cap on the estimated cost of all responses, in the unit of cost-per-1k-prompt and cost-per-1k-completion. Once it is exceeded, a warning is posted, budget-exceeded is emitted and input is dropped until reset-budget. 0 means no cap")
+        .minimum(0.0)
+        .default_value(0.0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecDouble::builder("total-cost")
+        .nick("Total cost")
+        .blurb("Estimated cost of the responses since the last reset-budget")
+        .read_only()
+        .build(),
+      glib::ParamSpecString::builder("user-template")
+        .nick("User template")
+        .blurb("Template of the user messages, in which {input} is replaced with the input. Like system-prompt, it can contain {now}, {pts} and {turn}. Defaults to sending the input as is")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("connect-timeout")
+        .nick("Connect timeout")
+        .blurb(&format!("Seconds to wait for a connection to the endpoint, so that an unreachable endpoint fails fast while timeout still bounds slow responses. 0 waits indefinitely. Defaults to {}. Takes effect when the element starts", DEFAULT_CONNECT_TIMEOUT))
+        .default_value(DEFAULT_CONNECT_TIMEOUT)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("stream-emit-eos-marker")
+        .nick("Emit end of message marker when streaming")
+        .blurb("Push an empty buffer with the MARKER flag after the last delta of a streamed response, so that downstream can tell where one message ends and the next begins")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("auth-header-name")
+        .nick("Authentication header name")
+        .blurb("Name of the header carrying the API key verbatim, e.g. X-Gateway-Key, for gateways expecting it there. Other headers of the provider, such as anthropic-version, are still sent. By default the provider's scheme is used: Authorization: Bearer for OpenAI and Ollama, api-key for Azure and x-api-key for Anthropic")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("min-request-interval")
+        .nick("Min request interval")
+        .blurb("Minimum number of milliseconds between the starts of successive requests, retries included. Requests due earlier wait, which spreads bursts of input out to stay under provider rate limits. 0 disables this")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("preload-history-file")
+        .nick("Preload history file")
+        .blurb("Path of a JSON file with an array of messages, like few-shot, that every conversation starts with after the system prompt and few-shot messages, e.g. a canned scenario. It is read on start and never written to")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecUInt::builder("max-history")
+        .nick("Max history")
+        .blurb("Maximum number of messages kept in the history of a conversation; the oldest are dropped first when a new input is added. The few-shot messages are kept, and not counted, as are the system messages with pin-system-prompt and the preloaded ones with pin-preloaded-history. 0 (the default) keeps the whole history")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("pin-system-prompt")
+        .nick("Pin system prompt")
+        .blurb("Keep the system messages when max-history trims the history. When false they are dropped like any other message once they are the oldest")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("pin-preloaded-history")
+        .nick("Pin preloaded history")
+        .blurb("Keep the messages of preload-history-file when max-history or max-history-bytes trims the history, like the few-shot messages. When false they are dropped like any other message once they are the oldest")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("stream-fallback")
+        .nick("Stream fallback")
+        .blurb("When stream is set and the server rejects the request with a 400 error mentioning stream, send it again without streaming, for servers that do not support server-sent events")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("stream-include-usage")
+        .nick("Stream include usage")
+        .blurb("Ask for the token usage at the end of streamed responses (stream_options.include_usage), for servers that only report it on request")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt64::builder("last-prompt-tokens")
+        .nick("Last prompt tokens")
+        .blurb("Prompt tokens of the most recent response, as reported by the provider")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt64::builder("last-completion-tokens")
+        .nick("Last completion tokens")
+        .blurb("Completion tokens of the most recent response. While a response is streamed this is a running estimate of one token per delta, replaced with the usage reported by the provider once it completes")
+        .read_only()
+        .build(),
+      glib::ParamSpecUInt::builder("flush-after-ms")
+        .nick("Flush after ms")
+        .blurb("When stream is not set, stream the response anyway but only push it whole if it arrives within this many milliseconds. Otherwise the text received once the time is up is pushed, followed by the rest as in stream mode, so downstream gets output within a latency budget. 0 disables this")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("tls-sni")
+        .nick("TLS SNI")
+        .blurb("Server name sent in TLS SNI and checked against the certificate, instead of the host of the endpoint URL, e.g. when the endpoint is an IP address behind split-horizon DNS. Takes effect when the element starts")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("host-override")
+        .nick("Host override")
+        .blurb("Value of the Host header of requests, instead of the host of the endpoint URL. Usually set together with tls-sni")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecUInt::builder("max-history-bytes")
+        .nick("Max history bytes")
+        .blurb("Maximum size of the history of a conversation, serialized as JSON; the oldest messages are dropped first when a new input is added, as a memory guard against huge inputs. The messages kept by max-history are kept here too, and the newest message always is. 0 (the default) disables this")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("conversation-tags")
+        .nick("Conversation tags")
+        .blurb("After each response, push a tag event with the history of the conversation as JSON in an extended-comment tag with the key openaichat-conversation, so that a downstream muxer can record the dialogue with the media")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("dedupe-responses")
+        .nick("Dedupe responses")
+        .blurb("Do not push a response identical to the previous one, which some models repeat under some prompts. Has no effect on deltas already pushed in stream mode")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("store-duplicates")
+        .nick("Store duplicates")
+        .blurb("Add responses suppressed by dedupe-responses to the history anyway, so that the model sees that it repeated itself")
+        .default_value(true)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("queue-output")
+        .nick("Queue output")
+        .blurb("Queue the responses and push them from the streaming thread, ahead of the next input buffer, instead of pushing them from the request task as soon as they arrive. Queued responses are pushed before EOS. Output on the audio and code pads is not queued")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecString::builder("prompt-prefix")
+        .nick("Prompt prefix")
+        .blurb("Text added before the input in each user message, e.g. \"User question: \". Applied before user-template")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("prompt-suffix")
+        .nick("Prompt suffix")
+        .blurb("Text added after the input in each user message. Applied before user-template")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("error-pad")
+        .nick("Error pad")
+        .blurb("Add an error src pad when the element starts, on which a JSON object with the status, message and request-id of each failed request is pushed, so that errors can be recorded or routed as data")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("code-pad")
+        .nick("Code pad")
+        .blurb("Add a code src pad when the element starts. While it is linked, fenced code blocks are taken out of each response and pushed on it, and the rest of the response on the src pad")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("require-src")
+        .nick("Require src")
+        .blurb("Fail with an error when the src pad is not linked, instead of sending the request and only updating the history, stats and signals, for elements that are only used for their side effects")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecInt::builder("default-priority")
+        .nick("Default priority")
+        .blurb("Priority of the requests for buffers without a priority in their OpenAiRequestParamsMeta. Queued requests are sent highest priority first, and a request in flight is preempted by a higher priority one, then sent again")
+        .default_value(0)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecEnum::builder::<ReasoningOutput>("reasoning-output")
+        .nick("Reasoning output")
+        .blurb("Where to put the reasoning returned by reasoning models in reasoning_content or reasoning: drop it, include it as reasoning in the response-received signal, or push it on the reasoning pad, which is added on start. The src pad only ever gets the answer")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecEnum::builder::<RequestContentType>("request-content-type")
+        .nick("Request content type")
+        .blurb("How request bodies are encoded: JSON, or application/x-www-form-urlencoded with a field for each top-level field of the JSON body, for gateways that only accept forms. Strings are sent as they are and other values as JSON")
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecBoolean::builder("tracing-spans")
+        .nick("Tracing spans")
+        .blurb("Wrap each request in a tracing span named openaichat_request, with the model, conversation ID, latency and HTTP status as fields. Only has an effect when the element is built with the tracing feature")
+        .default_value(false)
+        .mutable_ready()
+        .mutable_paused()
+        .mutable_playing()
+        .build(),
+      glib::ParamSpecString::builder("greeting")
+        .nick("Greeting")
+        .blurb("Opening line pushed on the src pad when the element starts with an empty conversation, without a request, as soon as downstream has the caps and segment")
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("record-greeting")
+        .nick("Record greeting")
+        .blurb("Add the greeting to the history as an assistant turn, so that the model knows what it opened with")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+      glib::ParamSpecBoolean::builder("allow-http")
+        .nick("Allow HTTP")
+        .blurb("Allow http:// endpoints, which send the API key in the clear, e.g. for a gateway on localhost. Always allowed with the ollama provider. Otherwise only https:// endpoints are used, so that a mistyped endpoint fails instead")
+        .default_value(false)
+        .mutable_ready()
+        .build(),
+    ]
+    });
+    PROPERTIES.as_ref()
+  }
+
+  fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+    self
+      .explicit_properties
+      .lock()
+      .unwrap()
+      .insert(pspec.name().to_owned());
+    let mut settings = self.settings.lock().unwrap();
+    match pspec.name() {
+      "model" => {
+        let model: String = value.get().unwrap();
+        if settings.allowed_models.is_empty() || settings.allowed_models.contains(&model) {
+          settings.model = model;
+        }
+        else {
+          gstreamer::warning!(
+            CAT,
+            "ignoring model {} which is not in allowed-models, keeping {}",
+            model,
+            settings.model
+          );
+        }
+      },
+      "system-prompt" => {
+        settings.system_prompt = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "temperature" => {
+        settings.temperature = Some(value.get().unwrap());
+      },
+      "output-role-prefix" => {
+        settings.output_role_prefix = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "emit-usage-messages" => {
+        settings.emit_usage_messages = value.get().unwrap();
+      },
+      "cost-per-1k-prompt" => {
+        settings.cost_per_1k_prompt = value.get().unwrap();
+      },
+      "cost-per-1k-completion" => {
+        settings.cost_per_1k_completion = value.get().unwrap();
+      },
+      "token-file" => {
+        settings.token_file = value.get::<Option<String>>().unwrap().map(PathBuf::from);
+      },
+      "conversation-timeout" => {
+        settings.conversation_timeout = value.get().unwrap();
+      },
+      "store-responses" => {
+        settings.store_responses = value.get().unwrap();
+      },
+      "n" => {
+        settings.n = value.get().unwrap();
+      },
+      "n-output" => {
+        settings.n_output = value.get().unwrap();
+      },
+      "seed" => {
+        let seed: i64 = value.get().unwrap();
+        settings.seed = (seed >= 0).then_some(seed);
+      },
+      "user-agent" => {
+        settings.user_agent = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "retry-on-empty" => {
+        settings.retry_on_empty = value.get().unwrap();
+      },
+      "max-retries" => {
+        settings.max_retries = value.get().unwrap();
+      },
+      "request-tag" => {
+        settings.request_tag = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "send-request-tag" => {
+        settings.send_request_tag = value.get().unwrap();
+      },
+      "timeout" => {
+        settings.timeout = value.get().unwrap();
+      },
+      "reassert-system-every" => {
+        settings.reassert_system_every = value.get().unwrap();
+      },
+      "dry-run" => {
+        settings.dry_run = value.get().unwrap();
+      },
+      "moderate-output" => {
+        settings.moderate_output = value.get().unwrap();
+      },
+      "moderation-replacement" => {
+        settings.moderation_replacement =
+          value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "max-conversations" => {
+        settings.max_conversations = value.get().unwrap();
+      },
+      "pool-idle-timeout" => {
+        settings.pool_idle_timeout = value.get().unwrap();
+      },
+      "pool-max-idle-per-host" => {
+        settings.pool_max_idle_per_host = value.get().unwrap();
+      },
+      "few-shot" => {
+        let few_shot = value.get::<Option<String>>().unwrap().unwrap_or_default();
+        if few_shot.is_empty() {
+          settings.few_shot.clear();
+        }
+        else {
+          match serde_json::from_str(&few_shot) {
+            Ok(few_shot) => settings.few_shot = few_shot,
+            Err(e) => gstreamer::warning!(CAT, "ignoring invalid few-shot messages: {}", e),
+          }
+        }
+      },
+      "stream" => {
+        settings.stream = value.get().unwrap();
+      },
+      "stream-bus-messages" => {
+        settings.stream_bus_messages = value.get().unwrap();
+      },
+      "max-response-bytes" => {
+        settings.max_response_bytes = value.get().unwrap();
+      },
+      "provider" => {
+        settings.provider = value.get().unwrap();
+      },
+      "emit-gaps" => {
+        settings.emit_gaps = value.get().unwrap();
+      },
+      "batch-size" => {
+        settings.batch_size = value.get().unwrap();
+      },
+      "join-separator" => {
+        settings.join_separator = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "base-url" => {
+        settings.base_url = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "deployment" => {
+        settings.deployment = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "api-version" => {
+        settings.api_version = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "prompt-cache-key" => {
+        settings.prompt_cache_key = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "extra-body" => {
+        let extra_body = value.get::<Option<String>>().unwrap().unwrap_or_default();
+        if extra_body.is_empty() {
+          settings.extra_body.clear();
+        }
+        else {
+          match serde_json::from_str(&extra_body) {
+            Ok(extra_body) => settings.extra_body = extra_body,
+            Err(e) => gstreamer::warning!(CAT, "ignoring invalid extra-body: {}", e),
+          }
+        }
+      },
+      "suppress-filtered" => {
+        settings.suppress_filtered = value.get().unwrap();
+      },
+      "responses-api" => {
+        settings.responses_api = value.get().unwrap();
+      },
+      "max-turns" => {
+        settings.max_turns = value.get().unwrap();
+      },
+      "closing-message" => {
+        settings.closing_message = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "echo-input" => {
+        settings.echo_input = value.get().unwrap();
+      },
+      "input-role-prefix" => {
+        settings.input_role_prefix = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "temperature-jitter" => {
+        settings.temperature_jitter = value.get().unwrap();
+      },
+      "modalities" => {
+        settings.modalities = value.get().unwrap();
+      },
+      "audio-voice" => {
+        settings.audio_voice = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "audio-format" => {
+        settings.audio_format = value.get().unwrap();
+      },
+      "pretty-request" => {
+        settings.pretty_request = value.get().unwrap();
+      },
+      "runtime" => {
+        settings.runtime = value.get().unwrap();
+      },
+      "runtime-threads" => {
+        settings.runtime_threads = value.get().unwrap();
+      },
+      "api-keys" => {
+        settings.api_keys = value
+          .get::<gstreamer::Array>()
+          .unwrap()
+          .iter()
+          .filter_map(|key| key.get::<String>().ok())
+          .collect();
+      },
+      "split-sentences" => {
+        settings.split_sentences = value.get().unwrap();
+      },
+      "sentence-delimiters" => {
+        settings.sentence_delimiters = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "allowed-models" => {
+        settings.allowed_models = value
+          .get::<Option<String>>()
+          .unwrap()
+          .unwrap_or_default()
+          .split(',')
+          .map(str::trim)
+          .filter(|model| !model.is_empty())
+          .map(ToOwned::to_owned)
+          .collect();
+        if !settings.allowed_models.is_empty()
+          && !settings.allowed_models.contains(&settings.model)
+        {
+          gstreamer::warning!(CAT, "current model {} is not in allowed-models", settings.model);
+        }
+      },
+      "compress-request" => {
+        settings.compress_request = value.get().unwrap();
+      },
+      "thinking-placeholder" => {
+        settings.thinking_placeholder = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "erase-placeholder" => {
+        settings.erase_placeholder = value.get().unwrap();
+      },
+      "choice-index" => {
+        settings.choice_index = value.get().unwrap();
+      },
+      "stream-mode" => {
+        settings.stream_mode = value.get().unwrap();
+      },
+      "output-envelope" => {
+        settings.output_envelope = value.get().unwrap();
+      },
+      "max-pending-requests" => {
+        settings.max_pending_requests = value.get().unwrap();
+      },
+      "inject-system-prompt" => {
+        settings.inject_system_prompt = value.get().unwrap();
+      },
+      "parallel-tool-calls" => {
+        settings.parallel_tool_calls = value.get().unwrap();
+      },
+      "assistant-name" => {
+        settings.assistant_name = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "user-name" => {
+        settings.user_name = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "on-error" => {
+        settings.on_error = value.get().unwrap();
+      },
+      "config-file" => {
+        settings.config_file = value.get::<Option<String>>().unwrap().map(PathBuf::from);
+      },
+      "output-regex" => {
+        let pattern = value.get::<Option<String>>().unwrap().unwrap_or_default();
+        if pattern.is_empty() {
+          settings.output_regex = None;
+        }
+        else {
+          match Regex::new(&pattern) {
+            Ok(regex) => settings.output_regex = Some(regex),
+            Err(e) => {
+              gstreamer::warning!(CAT, "ignoring invalid output-regex {:?}: {}", pattern, e);
+            },
+          }
+        }
+      },
+      "output-replace" => {
+        settings.output_replace = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "output-regex-history" => {
+        settings.output_regex_history = value.get().unwrap();
+      },
+      "endpoint" => {
+        settings.endpoint = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "request-method" => {
+        let method = value.get::<Option<String>>().unwrap().unwrap_or_default();
+        match Method::from_bytes(method.as_bytes()) {
+          Ok(method) => settings.request_method = method,
+          Err(e) => gstreamer::warning!(CAT, "ignoring invalid request-method {:?}: {}", method, e),
+        }
+      },
+      "reset-after-failures" => {
+        settings.reset_after_failures = value.get().unwrap();
+      },
+      "logprobs" => {
+        settings.logprobs = value.get().unwrap();
+      },
+      "top-logprobs" => {
+        settings.top_logprobs = value.get().unwrap();
+      },
+      "max-total-cost" => {
+        settings.max_total_cost = value.get().unwrap();
+      },
+      "user-template" => {
+        settings.user_template = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "connect-timeout" => {
+        settings.connect_timeout = value.get().unwrap();
+      },
+      "stream-emit-eos-marker" => {
+        settings.stream_emit_eos_marker = value.get().unwrap();
+      },
+      "auth-header-name" => {
+        settings.auth_header_name = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "min-request-interval" => {
+        settings.min_request_interval = value.get().unwrap();
+      },
+      "preload-history-file" => {
+        settings.preload_history_file = value.get::<Option<String>>().unwrap().map(PathBuf::from);
+      },
+      "max-history" => {
+        settings.max_history = value.get().unwrap();
+      },
+      "pin-system-prompt" => {
+        settings.pin_system_prompt = value.get().unwrap();
+      },
+      "pin-preloaded-history" => {
+        settings.pin_preloaded_history = value.get().unwrap();
+      },
+      "stream-fallback" => {
+        settings.stream_fallback = value.get().unwrap();
+      },
+      "stream-include-usage" => {
+        settings.stream_include_usage = value.get().unwrap();
+      },
+      "flush-after-ms" => {
+        settings.flush_after_ms = value.get().unwrap();
+      },
+      "tls-sni" => {
+        settings.tls_sni = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "host-override" => {
+        let host_override = value.get::<Option<String>>().unwrap().unwrap_or_default();
+        if HeaderValue::from_str(&host_override).is_ok() {
+          settings.host_override = host_override;
+        }
+        else {
+          gstreamer::warning!(CAT, "ignoring invalid host-override {:?}", host_override);
+        }
+      },
+      "max-history-bytes" => {
+        settings.max_history_bytes = value.get().unwrap();
+      },
+      "conversation-tags" => {
+        settings.conversation_tags = value.get().unwrap();
+      },
+      "dedupe-responses" => {
+        settings.dedupe_responses = value.get().unwrap();
+      },
+      "store-duplicates" => {
+        settings.store_duplicates = value.get().unwrap();
+      },
+      "queue-output" => {
+        settings.queue_output = value.get().unwrap();
+      },
+      "prompt-prefix" => {
+        settings.prompt_prefix = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "prompt-suffix" => {
+        settings.prompt_suffix = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "error-pad" => {
+        settings.error_pad = value.get().unwrap();
+      },
+      "code-pad" => {
+        settings.code_pad = value.get().unwrap();
+      },
+      "require-src" => {
+        settings.require_src = value.get().unwrap();
+      },
+      "default-priority" => {
+        settings.default_priority = value.get().unwrap();
+      },
+      "reasoning-output" => {
+        settings.reasoning_output = value.get().unwrap();
+      },
+      "request-content-type" => {
+        settings.request_content_type = value.get().unwrap();
+      },
+      "tracing-spans" => {
+        settings.tracing_spans = value.get().unwrap();
+      },
+      "greeting" => {
+        settings.greeting = value.get::<Option<String>>().unwrap().unwrap_or_default();
+      },
+      "record-greeting" => {
+        settings.record_greeting = value.get().unwrap();
+      },
+      "allow-http" => {
+        settings.allow_http = value.get().unwrap();
+      },
+      other => panic!("no such property: {}", other),
+    }
+  }
+
+  fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+    match pspec.name() {
+      "model" => {
+        let settings = self.settings.lock().unwrap();
+        settings.model.to_value()
+      },
+      "system-prompt" => {
+        let settings = self.settings.lock().unwrap();
+        settings.system_prompt.to_value()
+      },
+      "temperature" => {
+        let settings = self.settings.lock().unwrap();
+        settings.temperature.unwrap_or(DEFAULT_TEMPERATURE).to_value()
+      },
+      "output-role-prefix" => {
+        let settings = self.settings.lock().unwrap();
+        settings.output_role_prefix.to_value()
+      },
+      "emit-usage-messages" => {
+        let settings = self.settings.lock().unwrap();
+        settings.emit_usage_messages.to_value()
+      },
+      "cost-per-1k-prompt" => {
+        let settings = self.settings.lock().unwrap();
+        settings.cost_per_1k_prompt.to_value()
+      },
+      "cost-per-1k-completion" => {
+        let settings = self.settings.lock().unwrap();
+        settings.cost_per_1k_completion.to_value()
+      },
+      "token-file" => {
+        let settings = self.settings.lock().unwrap();
+        settings
+          .token_file
+          .as_ref()
+          .map(|path| path.to_string_lossy().into_owned())
+          .to_value()
+      },
+      "conversation-timeout" => {
+        let settings = self.settings.lock().unwrap();
+        settings.conversation_timeout.to_value()
+      },
+      "store-responses" => {
+        let settings = self.settings.lock().unwrap();
+        settings.store_responses.to_value()
+      },
+      "n" => {
+        let settings = self.settings.lock().unwrap();
+        settings.n.to_value()
+      },
+      "n-output" => {
+        let settings = self.settings.lock().unwrap();
+        settings.n_output.to_value()
+      },
+      "seed" => {
+        let settings = self.settings.lock().unwrap();
+        settings.seed.unwrap_or(-1).to_value()
+      },
+      "last-system-fingerprint" => {
+        let state = self.state.lock().unwrap();
+        state.last_system_fingerprint.to_value()
+      },
+      "user-agent" => {
+        let settings = self.settings.lock().unwrap();
+        settings.user_agent.to_value()
+      },
+      "retry-on-empty" => {
+        let settings = self.settings.lock().unwrap();
+        settings.retry_on_empty.to_value()
+      },
+      "max-retries" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_retries.to_value()
+      },
+      "request-tag" => {
+        let settings = self.settings.lock().unwrap();
+        settings.request_tag.to_value()
+      },
+      "send-request-tag" => {
+        let settings = self.settings.lock().unwrap();
+        settings.send_request_tag.to_value()
+      },
+      "timeout" => {
+        let settings = self.settings.lock().unwrap();
+        settings.timeout.to_value()
+      },
+      "reassert-system-every" => {
+        let settings = self.settings.lock().unwrap();
+        settings.reassert_system_every.to_value()
+      },
+      "dry-run" => {
+        let settings = self.settings.lock().unwrap();
+        settings.dry_run.to_value()
+      },
+      "moderate-output" => {
+        let settings = self.settings.lock().unwrap();
+        settings.moderate_output.to_value()
+      },
+      "moderation-replacement" => {
+        let settings = self.settings.lock().unwrap();
+        settings.moderation_replacement.to_value()
+      },
+      "max-conversations" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_conversations.to_value()
+      },
+      "last-response" => {
+        let state = self.state.lock().unwrap();
+        state.last_response.to_value()
+      },
+      "pool-idle-timeout" => {
+        let settings = self.settings.lock().unwrap();
+        settings.pool_idle_timeout.to_value()
+      },
+      "pool-max-idle-per-host" => {
+        let settings = self.settings.lock().unwrap();
+        settings.pool_max_idle_per_host.to_value()
+      },
+      "few-shot" => {
+        let settings = self.settings.lock().unwrap();
+        serde_json::to_string(&settings.few_shot).unwrap().to_value()
+      },
+      "stream" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream.to_value()
+      },
+      "stream-bus-messages" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream_bus_messages.to_value()
+      },
+      "max-response-bytes" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_response_bytes.to_value()
+      },
+      "history-length" => {
+        (self.stats.history_length.load(Ordering::Relaxed) as u32).to_value()
+      },
+      "turn-count" => {
+        self.stats.turn_count.load(Ordering::Relaxed).to_value()
+      },
+      "active-conversations" => {
+        (self.stats.active_conversations.load(Ordering::Relaxed) as u32).to_value()
+      },
+      "evicted-conversations" => {
+        self.stats.evicted_conversations.load(Ordering::Relaxed).to_value()
+      },
+      "provider" => {
+        let settings = self.settings.lock().unwrap();
+        settings.provider.to_value()
+      },
+      "emit-gaps" => {
+        let settings = self.settings.lock().unwrap();
+        settings.emit_gaps.to_value()
+      },
+      "batch-size" => {
+        let settings = self.settings.lock().unwrap();
+        settings.batch_size.to_value()
+      },
+      "join-separator" => {
+        let settings = self.settings.lock().unwrap();
+        settings.join_separator.to_value()
+      },
+      "base-url" => {
+        let settings = self.settings.lock().unwrap();
+        settings.base_url.to_value()
+      },
+      "deployment" => {
+        let settings = self.settings.lock().unwrap();
+        settings.deployment.to_value()
+      },
+      "api-version" => {
+        let settings = self.settings.lock().unwrap();
+        settings.api_version.to_value()
+      },
+      "prompt-cache-key" => {
+        let settings = self.settings.lock().unwrap();
+        settings.prompt_cache_key.to_value()
+      },
+      "extra-body" => {
+        let settings = self.settings.lock().unwrap();
+        serde_json::to_string(&settings.extra_body).unwrap().to_value()
+      },
+      "suppress-filtered" => {
+        let settings = self.settings.lock().unwrap();
+        settings.suppress_filtered.to_value()
+      },
+      "responses-api" => {
+        let settings = self.settings.lock().unwrap();
+        settings.responses_api.to_value()
+      },
+      "max-turns" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_turns.to_value()
+      },
+      "closing-message" => {
+        let settings = self.settings.lock().unwrap();
+        settings.closing_message.to_value()
+      },
+      "echo-input" => {
+        let settings = self.settings.lock().unwrap();
+        settings.echo_input.to_value()
+      },
+      "input-role-prefix" => {
+        let settings = self.settings.lock().unwrap();
+        settings.input_role_prefix.to_value()
+      },
+      "temperature-jitter" => {
+        let settings = self.settings.lock().unwrap();
+        settings.temperature_jitter.to_value()
+      },
+      "modalities" => {
+        let settings = self.settings.lock().unwrap();
+        settings.modalities.to_value()
+      },
+      "audio-voice" => {
+        let settings = self.settings.lock().unwrap();
+        settings.audio_voice.to_value()
+      },
+      "audio-format" => {
+        let settings = self.settings.lock().unwrap();
+        settings.audio_format.to_value()
+      },
+      "pretty-request" => {
+        let settings = self.settings.lock().unwrap();
+        settings.pretty_request.to_value()
+      },
+      "runtime" => {
+        let settings = self.settings.lock().unwrap();
+        settings.runtime.to_value()
+      },
+      "runtime-threads" => {
+        let settings = self.settings.lock().unwrap();
+        settings.runtime_threads.to_value()
+      },
+      "api-keys" => {
+        let settings = self.settings.lock().unwrap();
+        gstreamer::Array::new(&settings.api_keys).to_value()
+      },
+      "split-sentences" => {
+        let settings = self.settings.lock().unwrap();
+        settings.split_sentences.to_value()
+      },
+      "sentence-delimiters" => {
+        let settings = self.settings.lock().unwrap();
+        settings.sentence_delimiters.to_value()
+      },
+      "allowed-models" => {
+        let settings = self.settings.lock().unwrap();
+        settings.allowed_models.join(",").to_value()
+      },
+      "last-request-id" => {
+        let state = self.state.lock().unwrap();
+        state.last_request_id.to_value()
+      },
+      "compress-request" => {
+        let settings = self.settings.lock().unwrap();
+        settings.compress_request.to_value()
+      },
+      "thinking-placeholder" => {
+        let settings = self.settings.lock().unwrap();
+        settings.thinking_placeholder.to_value()
+      },
+      "erase-placeholder" => {
+        let settings = self.settings.lock().unwrap();
+        settings.erase_placeholder.to_value()
+      },
+      "choice-index" => {
+        let settings = self.settings.lock().unwrap();
+        settings.choice_index.to_value()
+      },
+      "stream-mode" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream_mode.to_value()
+      },
+      "output-envelope" => {
+        let settings = self.settings.lock().unwrap();
+        settings.output_envelope.to_value()
+      },
+      "max-pending-requests" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_pending_requests.to_value()
+      },
+      "inject-system-prompt" => {
+        let settings = self.settings.lock().unwrap();
+        settings.inject_system_prompt.to_value()
+      },
+      "parallel-tool-calls" => {
+        let settings = self.settings.lock().unwrap();
+        settings.parallel_tool_calls.to_value()
+      },
+      "assistant-name" => {
+        let settings = self.settings.lock().unwrap();
+        settings.assistant_name.to_value()
+      },
+      "user-name" => {
+        let settings = self.settings.lock().unwrap();
+        settings.user_name.to_value()
+      },
+      "on-error" => {
+        let settings = self.settings.lock().unwrap();
+        settings.on_error.to_value()
+      },
+      "last-first-token-ms" => {
+        self.stats.last_first_token_ms.load(Ordering::Relaxed).to_value()
+      },
+      "config-file" => {
+        let settings = self.settings.lock().unwrap();
+        settings
+          .config_file
+          .as_ref()
+          .map(|path| path.to_string_lossy().into_owned())
+          .to_value()
+      },
+      "output-regex" => {
+        let settings = self.settings.lock().unwrap();
+        settings.output_regex.as_ref().map(Regex::as_str).to_value()
+      },
+      "output-replace" => {
+        let settings = self.settings.lock().unwrap();
+        settings.output_replace.to_value()
+      },
+      "output-regex-history" => {
+        let settings = self.settings.lock().unwrap();
+        settings.output_regex_history.to_value()
+      },
+      "endpoint" => {
+        let settings = self.settings.lock().unwrap();
+        settings.endpoint.to_value()
+      },
+      "request-method" => {
+        let settings = self.settings.lock().unwrap();
+        settings.request_method.as_str().to_value()
+      },
+      "reset-after-failures" => {
+        let settings = self.settings.lock().unwrap();
+        settings.reset_after_failures.to_value()
+      },
+      "logprobs" => {
+        let settings = self.settings.lock().unwrap();
+        settings.logprobs.to_value()
+      },
+      "top-logprobs" => {
+        let settings = self.settings.lock().unwrap();
+        settings.top_logprobs.to_value()
+      },
+      "max-total-cost" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_total_cost.to_value()
+      },
+      "total-cost" => {
+        let state = self.state.lock().unwrap();
+        state.total_cost.to_value()
+      },
+      "user-template" => {
+        let settings = self.settings.lock().unwrap();
+        settings.user_template.to_value()
+      },
+      "connect-timeout" => {
+        let settings = self.settings.lock().unwrap();
+        settings.connect_timeout.to_value()
+      },
+      "stream-emit-eos-marker" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream_emit_eos_marker.to_value()
+      },
+      "auth-header-name" => {
+        let settings = self.settings.lock().unwrap();
+        settings.auth_header_name.to_value()
+      },
+      "min-request-interval" => {
+        let settings = self.settings.lock().unwrap();
+        settings.min_request_interval.to_value()
+      },
+      "preload-history-file" => {
+        let settings = self.settings.lock().unwrap();
+        settings
+          .preload_history_file
+          .as_ref()
+          .map(|path| path.to_string_lossy().into_owned())
+          .to_value()
+      },
+      "max-history" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_history.to_value()
+      },
+      "pin-system-prompt" => {
+        let settings = self.settings.lock().unwrap();
+        settings.pin_system_prompt.to_value()
+      },
+      "pin-preloaded-history" => {
+        let settings = self.settings.lock().unwrap();
+        settings.pin_preloaded_history.to_value()
+      },
+      "stream-fallback" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream_fallback.to_value()
+      },
+      "stream-include-usage" => {
+        let settings = self.settings.lock().unwrap();
+        settings.stream_include_usage.to_value()
+      },
+      "last-prompt-tokens" => self.stats.last_prompt_tokens.load(Ordering::Relaxed).to_value(),
+      "last-completion-tokens" => {
+        self.stats.last_completion_tokens.load(Ordering::Relaxed).to_value()
+      },
+      "flush-after-ms" => {
+        let settings = self.settings.lock().unwrap();
+        settings.flush_after_ms.to_value()
+      },
+      "tls-sni" => {
+        let settings = self.settings.lock().unwrap();
+        settings.tls_sni.to_value()
+      },
+      "host-override" => {
+        let settings = self.settings.lock().unwrap();
+        settings.host_override.to_value()
+      },
+      "max-history-bytes" => {
+        let settings = self.settings.lock().unwrap();
+        settings.max_history_bytes.to_value()
+      },
+      "conversation-tags" => {
+        let settings = self.settings.lock().unwrap();
+        settings.conversation_tags.to_value()
+      },
+      "dedupe-responses" => {
+        let settings = self.settings.lock().unwrap();
+        settings.dedupe_responses.to_value()
+      },
+      "store-duplicates" => {
+        let settings = self.settings.lock().unwrap();
+        settings.store_duplicates.to_value()
+      },
+      "queue-output" => {
+        let settings = self.settings.lock().unwrap();
+        settings.queue_output.to_value()
+      },
+      "prompt-prefix" => {
+        let settings = self.settings.lock().unwrap();
+        settings.prompt_prefix.to_value()
+      },
+      "prompt-suffix" => {
+        let settings = self.settings.lock().unwrap();
+        settings.prompt_suffix.to_value()
+      },
+      "error-pad" => {
+        let settings = self.settings.lock().unwrap();
+        settings.error_pad.to_value()
+      },
+      "code-pad" => {
+        let settings = self.settings.lock().unwrap();
+        settings.code_pad.to_value()
+      },
+      "require-src" => {
+        let settings = self.settings.lock().unwrap();
+        settings.require_src.to_value()
+      },
+      "default-priority" => {
+        let settings = self.settings.lock().unwrap();
+        settings.default_priority.to_value()
+      },
+      "reasoning-output" => {
+        let settings = self.settings.lock().unwrap();
+        settings.reasoning_output.to_value()
+      },
+      "request-content-type" => {
+        let settings = self.settings.lock().unwrap();
+        settings.request_content_type.to_value()
+      },
+      "tracing-spans" => {
+        let settings = self.settings.lock().unwrap();
+        settings.tracing_spans.to_value()
+      },
+      "greeting" => {
+        let settings = self.settings.lock().unwrap();
+        settings.greeting.to_value()
+      },
+      "record-greeting" => {
+        let settings = self.settings.lock().unwrap();
+        settings.record_greeting.to_value()
+      },
+      "allow-http" => {
+        let settings = self.settings.lock().unwrap();
+        settings.allow_http.to_value()
+      },
+      other => panic!("no such property: {}", other),
+    }
+  }
+
+  fn signals() -> &'static [Signal] {
+    static SIGNALS: Lazy<Vec<Signal>> =
+      Lazy::new(|| {
+        vec![
+          Signal::builder("conversation-ended").build(),
+          Signal::builder("conversation-evicted")
+            .param_types([String::static_type()])
+            .build(),
+          Signal::builder("recovered")
+            .param_types([u32::static_type()])
+            .build(),
+          Signal::builder("request-started")
+            .param_types([Structure::static_type()])
+            .build(),
+          Signal::builder("response-received")
+            .param_types([Structure::static_type()])
+            .build(),
+          Signal::builder("response-blocked")
+            .param_types([Structure::static_type()])
+            .build(),
+          Signal::builder("response-filtered")
+            .param_types([Structure::static_type()])
+            .build(),
+          // Every handler gets the content as received. The string returned by the last handler
+          // that does not return None replaces it.
+          Signal::builder("transform-response")
+            .param_types([String::static_type()])
+            .return_type::<Option<String>>()
+            .accumulator(|_, accumulated, value| {
+              if value.get::<Option<String>>().is_ok_and(|content| content.is_some()) {
+                *accumulated = value.clone();
+              }
+              true
+            })
+            .build(),
+          Signal::builder("cancel")
+            .action()
+            .class_handler(|_, args| {
+              let element = args[0].get::<super::OpenaiChatFilter>().unwrap();
+              element.imp().cancel();
+              None
+            })
+            .build(),
+          Signal::builder("budget-exceeded")
+            .param_types([f64::static_type()])
+            .build(),
+          Signal::builder("reset-budget")
+            .action()
+            .class_handler(|_, args| {
+              let element = args[0].get::<super::OpenaiChatFilter>().unwrap();
+              let mut state = element.imp().state.lock().unwrap();
+              state.total_cost = 0.0;
+              state.budget_exceeded = false;
+              None
+            })
+            .build(),
+          Signal::builder("ping")
+            .action()
+            .return_type::<bool>()
+            .class_handler(|_, args| {
+              let element = args[0].get::<super::OpenaiChatFilter>().unwrap();
+              Some(element.imp().ping().to_value())
+            })
+            .build(),
+        ]
+      });
+    SIGNALS.as_ref()
+  }
+}
+
+impl GstObjectImpl for OpenaiChatFilter {}
+
+impl ElementImpl for OpenaiChatFilter {
+  fn metadata() -> Option<&'static ElementMetadata> {
+    static ELEMENT_METADATA: Lazy<ElementMetadata> = Lazy::new(|| {
+      ElementMetadata::new(
+        "OpenAI Chat API element",
+        "Effect/Text",
+        "Sink a text buffer, send it to the OpenAI Chat API, and source the response as a text buffer",
+        "Jasper Hugo <jasper@avstack.io>",
+      )
+    });
+
+    Some(&*ELEMENT_METADATA)
+  }
+
+  fn pad_templates() -> &'static [PadTemplate] {
+    static PAD_TEMPLATES: Lazy<Vec<PadTemplate>> = Lazy::new(|| {
+      let src_caps: Caps = [CAPS.clone(), JSON_CAPS.clone()].into_iter().collect();
+      let src_pad_template =
+        PadTemplate::new("src", PadDirection::Src, PadPresence::Always, &src_caps).unwrap();
+
+      let sink_pad_template = gstreamer::PadTemplate::new(
+        "sink",
+        gstreamer::PadDirection::Sink,
+        gstreamer::PadPresence::Always,
+        &CAPS,
+      )
+      .unwrap();
+
+      let audio_pad_template =
+        PadTemplate::new("audio", PadDirection::Src, PadPresence::Sometimes, &AUDIO_CAPS).unwrap();
+
+      let code_pad_template =
+        PadTemplate::new("code", PadDirection::Src, PadPresence::Sometimes, &CAPS).unwrap();
+
+      let error_pad_template =
+        PadTemplate::new("error", PadDirection::Src, PadPresence::Sometimes, &JSON_CAPS).unwrap();
+
+      let reasoning_pad_template =
+        PadTemplate::new("reasoning", PadDirection::Src, PadPresence::Sometimes, &CAPS).unwrap();
+
+      vec![
+        src_pad_template,
+        sink_pad_template,
+        audio_pad_template,
+        code_pad_template,
+        error_pad_template,
+        reasoning_pad_template,
+      ]
+    });
+
+    PAD_TEMPLATES.as_ref()
+  }
+}
+
+impl BaseTransformImpl for OpenaiChatFilter {
+  const MODE: BaseTransformMode = BaseTransformMode::NeverInPlace;
+  const PASSTHROUGH_ON_SAME_CAPS: bool = false;
+  const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
+
+  fn start(&self) -> Result<(), ErrorMessage> {
+    gstreamer::debug!(CAT, "start()");
+    let config_file = self.settings.lock().unwrap().config_file.clone();
+    if let Some(config_file) = config_file {
+      self.apply_config_file(&config_file)?;
+    }
+    let (error_pad, code_pad, reasoning_output, modalities) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.error_pad, settings.code_pad, settings.reasoning_output, settings.modalities)
+    };
+    if error_pad {
+      self.add_sometimes_pad("error");
+    }
+    if code_pad {
+      self.add_sometimes_pad("code");
+    }
+    if modalities == Modalities::TextAudio {
+      self.add_sometimes_pad("audio");
+    }
+    if reasoning_output == ReasoningOutput::Pad {
+      self.add_sometimes_pad("reasoning");
+    }
+    let (greeting, record_greeting) = {
+      let settings = self.settings.lock().unwrap();
+      (settings.greeting.clone(), settings.record_greeting)
+    };
+    if !greeting.is_empty() {
+      let mut state = self.state.lock().unwrap();
+      let started = state
+        .conversations
+        .get(&state.conversation_id)
+        .is_some_and(|conversation| !conversation.history.is_empty());
+      if !started {
+        state.greeting_turn = record_greeting.then(|| greeting.clone());
+        state.greeting = Some(greeting);
+      }
+    }
+    let preload_history_file = self.settings.lock().unwrap().preload_history_file.clone();
+    self.state.lock().unwrap().preloaded_history = match preload_history_file {
+      Some(path) => Arc::new(read_history(&path)?),
+      None => Arc::default(),
+    };
+    Ok(())
+  }
+
+  fn stop(&self) -> Result<(), ErrorMessage> {
+    gstreamer::debug!(CAT, "stop()");
+    self.cancel();
+    *self.client.lock().unwrap() = None;
+    *self.requests.lock().unwrap() = None;
+    *self.request_task.lock().unwrap() = None;
+    self.output_queue.lock().unwrap().clear();
+    Ok(())
+  }
+
+  fn sink_event(&self, event: Event) -> bool {
+    if let EventView::FlushStart(_) = event.view() {
+      gstreamer::debug!(CAT, "flushing, cancelling requests in flight");
+      self.cancel();
+      self.output_queue.lock().unwrap().clear();
+    }
+    if let EventView::Eos(_) = event.view() {
+      let settings = self.settings.lock().unwrap().clone();
+      if settings.queue_output {
+        gstreamer::debug!(CAT, "EOS, pushing the responses to the queued requests first");
+        self.finish_requests(&settings);
+        while let Some(buffer) = self.dequeue_output() {
+          if let Err(e) = self.obj().src_pad().push(buffer) {
+            gstreamer::debug!(CAT, "failed to push response: {}", e);
+            break;
+          }
+        }
+      }
+    }
+    if let EventView::CustomDownstream(custom) = event.view() {
+      if let Some(structure) = custom.structure().filter(|s| s.has_name(CONVERSATION_EVENT)) {
+        match structure.get::<String>("id") {
+          Ok(conversation_id) => {
+            gstreamer::debug!(CAT, "switching to conversation {:?}", conversation_id);
+            let mut state = self.state.lock().unwrap();
+            state.conversation_id = conversation_id;
+            self.stats.update(&state);
+          },
+          Err(e) => {
+            gstreamer::warning!(CAT, "ignoring invalid {} event: {}", CONVERSATION_EVENT, e);
+          },
+        }
+        return true;
+      }
+      if let Some(structure) = custom.structure().filter(|s| s.has_name(SET_MAX_TOKENS_EVENT)) {
+        match structure.get::<u32>("max-tokens") {
+          Ok(max_tokens) => {
+            gstreamer::debug!(CAT, "limiting the next request to {} tokens", max_tokens);
+            self.state.lock().unwrap().max_tokens_override = Some(max_tokens);
+          },
+          Err(e) => {
+            gstreamer::warning!(CAT, "ignoring invalid {} event: {}", SET_MAX_TOKENS_EVENT, e);
+          },
+        }
+        return true;
+      }
+    }
+    let segment = matches!(event.view(), EventView::Segment(_));
+    let forwarded = self.parent_sink_event(event);
+    // The greeting waits for the segment, which follows the caps, so that downstream can take it.
+    if segment && forwarded {
+      let greeting = self.state.lock().unwrap().greeting.take();
+      if let Some(greeting) = greeting {
+        gstreamer::debug!(CAT, "pushing greeting");
+        if let Err(e) = self.obj().src_pad().push(text_buffer(&format!("{}\n", greeting))) {
+          gstreamer::debug!(CAT, "failed to push greeting: {}", e);
+        }
+      }
+    }
+    forwarded
+  }
+
+  fn transform_caps(
+    &self,
+    direction: PadDirection,
+    caps: &Caps,
+    maybe_filter: Option<&Caps>,
+  ) -> Option<Caps> {
+    let src_caps = src_caps(&self.settings.lock().unwrap());
+    let (from, to) = if direction == PadDirection::Sink {
+      (&*CAPS, src_caps)
+    }
+    else {
+      (src_caps, &*CAPS)
+    };
+    let mut other_caps = if from == to {
+      // Text is transformed into text, so the caps allowed on one pad restrict the other pad in
+      // the same way, whichever direction the query comes from.
+      caps.intersect_with_mode(to, CapsIntersectMode::First)
+    }
+    else if caps.can_intersect(from) {
+      to.clone()
+    }
+    else {
+      Caps::new_empty()
+    };
+    if let Some(filter) = maybe_filter {
+      other_caps = filter.intersect_with_mode(&other_caps, CapsIntersectMode::First);
+    }
+    gstreamer::debug!(
+      CAT,
+      "transform_caps({:?}): {} -> {}",
+      direction,
+      caps,
+      other_caps
+    );
+    Some(other_caps)
+  }
+
+  fn generate_output(&self) -> Result<GenerateOutputSuccess, FlowError> {
+    // With queue-output, completed responses are pushed from here, ahead of the next input.
+    if let Some(buffer) = self.dequeue_output() {
+      return Ok(GenerateOutputSuccess::Buffer(buffer));
+    }
+    if let Some(buffer) = self.take_queued_buffer() {
+      let buffer_reader = buffer.as_ref().map_readable().unwrap();
+
+      let content = str::from_utf8(buffer_reader.as_slice()).unwrap();
+
+      let mut settings = self.settings.lock().unwrap().clone();
+      let params = buffer
+        .meta::<OpenAiRequestParamsMeta>()
+        .map(|meta| meta.params().clone())
+        .unwrap_or_default();
+      if let Some(temperature) = params.temperature {
+        settings.temperature = Some(temperature);
+        settings.temperature_jitter = 0.0;
+      }
+      if let Some(seed) = params.seed {
+        settings.seed = Some(seed);
+      }
+
+      let received = Instant::now();
+      let timed_out = {
+        let mut state = self.state.lock().unwrap();
+        let conversation_id = state.conversation_id.clone();
+        state.conversations.get_mut(&conversation_id).is_some_and(|conversation| {
+          let last_input = conversation.last_input.replace(received);
+          settings.conversation_timeout > 0
+            && !conversation.history.is_empty()
+            && last_input.is_some_and(|last_input| {
+              received.duration_since(last_input)
+                >= Duration::from_secs(settings.conversation_timeout.into())
+            })
+        })
+      };
+      if timed_out {
+        gstreamer::debug!(CAT, "conversation timed out, starting a new one");
+        self.end_conversation();
+      }
+
+      let content = if settings.batch_size > 1 {
+        let mut state = self.state.lock().unwrap();
+        state.pending_input.push(content.to_owned());
+        if state.pending_input.len() < settings.batch_size as usize {
+          return Ok(GenerateOutputSuccess::NoOutput);
+        }
+        mem::take(&mut state.pending_input).join(&settings.join_separator)
+      }
+      else {
+        content.to_owned()
+      };
+
+      if self.state.lock().unwrap().budget_exceeded {
+        gstreamer::debug!(CAT, "max-total-cost exceeded, dropping input");
+        return Ok(GenerateOutputSuccess::NoOutput);
+      }
+
+      if settings.require_src && !self.obj().src_pad().is_linked() {
+        gstreamer::element_imp_error!(
+          self,
+          gstreamer::CoreError::Pad,
+          ["src pad is not linked and require-src is set"]
+        );
+        return Err(FlowError::NotLinked);
+      }
+
+      // A dry run never sends the request, so it works without a key.
+      let api_key = if settings.dry_run {
+        String::new()
+      }
+      else {
+        match self.api_key(&settings) {
+          Ok(api_key) => api_key,
+          Err(e) => {
+            gstreamer::element_imp_error!(self, gstreamer::ResourceError::OpenRead, ["{}", e]);
+            return Err(FlowError::Error);
+          },
+        }
+      };
+
+      let endpoint: Uri = match chat_endpoint(&settings).parse() {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+          gstreamer::element_imp_error!(
+            self,
+            gstreamer::ResourceError::Settings,
+            ["Invalid endpoint: {}", e]
+          );
+          return Err(FlowError::Error);
+        },
+      };
+
+      let echo = settings
+        .echo_input
+        .then(|| format!("{}{}\n", settings.input_role_prefix, content));
+      let passthrough =
+        (settings.on_error == OnError::Passthrough).then(|| format!("{}\n", content));
+
+      let (conversation_id, messages, new_messages_start, previous_response_id, max_tokens) = {
+        let mut state = self.state.lock().unwrap();
+        let max_tokens = params.max_tokens.or_else(|| state.max_tokens_override.take());
+        let conversation_id = state.conversation_id.clone();
+        let preloaded_history = state.preloaded_history.clone();
+        let greeting = state.greeting_turn.take();
+        let conversation = state.conversation(&conversation_id, settings.max_conversations);
+        conversation.last_input = Some(received);
+        let reassert_system_prompt = settings.reassert_system_every > 0
+          && conversation.turns_since_system_prompt >= settings.reassert_system_every;
+        let start_of_conversation = conversation.history.is_empty();
+        let new_messages_start = conversation.history.len();
+        let turn = conversation.turn_count + 1;
+        let expand = |text: &str| expand_placeholders(text, buffer.pts(), turn);
+        // The history keeps the system prompt and few-shot messages it started with, so every
+        // request of a conversation shares the same prefix and can hit the prompt cache.
+        if settings.inject_system_prompt && (start_of_conversation || reassert_system_prompt) {
+          conversation
+            .history
+            .extend(system_messages(&settings.system_prompt).into_iter().map(|message| {
+              OpenaiChatCompletionMessage {
+                content: expand(&message.content),
+                ..message
+              }
+            }));
+          conversation.turns_since_system_prompt = 0;
+        }
+        if start_of_conversation {
+          let seed_start = conversation.history.len();
+          conversation.history.extend(settings.few_shot.iter().cloned());
+          let few_shot_end = conversation.history.len();
+          conversation.history.extend(preloaded_history.iter().cloned());
+          let seed_end = if settings.pin_preloaded_history {
+            conversation.history.len()
+          }
+          else {
+            few_shot_end
+          };
+          conversation.seed = seed_start..seed_end;
+          conversation
+            .history
+            .extend(greeting.map(|greeting| OpenaiChatCompletionMessage {
+              role: "assistant".into(),
+              content: greeting,
+              name: settings.assistant_name.clone(),
+              ..Default::default()
+            }));
+        }
+        conversation.turns_since_system_prompt += 1;
+        let content = format!("{}{}{}", settings.prompt_prefix, content, settings.prompt_suffix);
+        let content = if settings.user_template.is_empty() {
+          content
+        }
+        else {
+          // Expand before inserting the input, so placeholders in the input are kept verbatim.
+          expand(&settings.user_template).replace("{input}", &content)
+        };
+        conversation.history.push(OpenaiChatCompletionMessage {
+          role: "user".into(),
+          content,
+          name: settings.user_name.clone(),
+          ..Default::default()
+        });
+        let trimmed = trim_history(&mut conversation.history, &mut conversation.seed, &settings);
+        let request = (
+          conversation_id,
+          conversation.history.clone(),
+          new_messages_start.saturating_sub(trimmed),
+          conversation.previous_response_id.clone(),
+          max_tokens,
+        );
+        self.stats.update(&state);
+        request
+      };
+      // Emitted without the state lock, so that handlers can use the element.
+      let evicted = mem::take(&mut self.state.lock().unwrap().evicted);
+      for evicted in evicted {
+        self.obj().emit_by_name::<()>("conversation-evicted", &[&evicted]);
+      }
+
+      let temperature = if settings.temperature_jitter > 0.0 {
+        let temperature = jittered_temperature(
+          settings.temperature.unwrap_or(DEFAULT_TEMPERATURE),
+          settings.temperature_jitter,
+        );
+        gstreamer::debug!(CAT, "jittered temperature: {}", temperature);
+        Some(temperature)
+      }
+      else {
+        settings.temperature
+      };
+      let metadata = (settings.send_request_tag && !settings.request_tag.is_empty())
+        .then(|| HashMap::from([("request_tag".to_owned(), settings.request_tag.clone())]));
+      let prompt_cache_key =
+        (!settings.prompt_cache_key.is_empty()).then(|| settings.prompt_cache_key.clone());
+      let request_body = if settings.responses_api {
+        // The earlier turns are already stored with the previous response.
+        let input = match &previous_response_id {
+          Some(_) => messages[new_messages_start..].to_vec(),
+          None => messages,
+        };
+        let request_body = OpenaiResponsesRequest {
+          model: settings.model.clone(),
+          input: settings.provider.messages(input),
+          previous_response_id,
+          max_output_tokens: max_tokens,
+          temperature,
+          metadata,
+          prompt_cache_key,
+        };
+        request_json(&settings, &request_body)
+      }
+      else if settings.provider == Provider::Anthropic {
+        let request_body = anthropic_request(
+          &settings.model,
+          messages,
+          max_tokens,
+          temperature,
+          settings.stream || settings.flush_after_ms > 0,
+        );
+        request_json(&settings, &request_body)
+      }
+      else {
+        let request_body = OpenaiChatCompletionRequest {
+          model: settings.model.clone(),
+          messages: settings.provider.messages(messages),
+          temperature,
+          n: (settings.n > 1).then_some(settings.n),
+          seed: settings.seed,
+          max_tokens,
+          stream: (settings.stream || settings.flush_after_ms > 0).then_some(true),
+          metadata,
+          prompt_cache_key,
+          modalities: (settings.modalities == Modalities::TextAudio)
+            .then(|| vec!["text".to_owned(), "audio".to_owned()]),
+          audio: (settings.modalities == Modalities::TextAudio).then(|| {
+            OpenaiChatCompletionAudioConfig {
+              voice: settings.audio_voice.clone(),
+              format: settings.audio_format.api_name().to_owned(),
+            }
+          }),
+          logprobs: settings.logprobs.then_some(true),
+          top_logprobs: (settings.logprobs && settings.top_logprobs > 0)
+            .then_some(settings.top_logprobs),
+          stream_options: (settings.stream && settings.stream_include_usage).then_some(
+            OpenaiStreamOptions {
+              include_usage: true,
+            },
+          ),
+        };
+        request_json(&settings, &request_body)
+      };
+
+      if settings.dry_run {
+        let request_body = display_json(&settings, &request_body);
+        gstreamer::info!(
+          CAT,
+          "dry run: POST {} (api-key: <redacted>): {}",
+          endpoint,
+          request_body
+        );
+        return Ok(GenerateOutputSuccess::Buffer(text_buffer(&format!(
+          "{}\n",
+          request_body
+        ))));
+      }
+
+      let request_info = Structure::builder("openaichat-request")
+        .field("model", &settings.model)
+        .field("request-tag", &settings.request_tag)
+        .build();
+      self.obj().emit_by_name::<()>("request-started", &[&request_info]);
+
+      let pending_request = PendingRequest {
+        element: self.obj().clone(),
+        cancellation: self.state.lock().unwrap().cancellation.child_token(),
+        endpoint,
+        src_pad: SrcOutput {
+          pad: self.obj().src_pad().to_owned(),
+          queue: settings.queue_output.then(|| self.output_queue.clone()),
+        },
+        state: self.state.clone(),
+        stats: self.stats.clone(),
+        client: self.client(&settings),
+        settings: settings.clone(),
+        api_key,
+        conversation_id,
+        request_body,
+        input_pts: buffer.pts(),
+        input_duration: buffer.duration(),
+        echo,
+        passthrough,
+        priority: params.priority.unwrap_or(settings.default_priority),
+      };
+      self.queue_request(&settings, pending_request)?;
+
+      Ok(GenerateOutputSuccess::NoOutput)
+    }
+    else {
+      gstreamer::debug!(CAT, "generate_output(): no queued buffers to take");
+      Ok(GenerateOutputSuccess::NoOutput)
+    }
+  }
+}
+
+/// Parses a config file as TOML or JSON, depending on its extension.
+fn parse_config(
+  path: &Path,
+  config: &str,
+) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+  if path.extension().is_some_and(|extension| extension == "toml") {
+    toml::from_str(config).map_err(|e| e.to_string())
+  }
+  else {
+    serde_json::from_str(config).map_err(|e| e.to_string())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn keys() -> Vec<String> {
+    vec!["a".into(), "b".into(), "c".into()]
+  }
+
+  #[test]
+  fn next_api_key_is_round_robin() {
+    let mut state = State::default();
+    let picked: Vec<String> = (0..4).map(|_| state.next_api_key(&keys())).collect();
+    assert_eq!(picked, ["a", "b", "c", "a"]);
+  }
+
+  #[test]
+  fn next_api_key_skips_keys_in_cooldown() {
+    let mut state = State::default();
+    let until = Instant::now() + Duration::from_secs(60);
+    state.api_key_cooldowns.insert("b".into(), until);
+    let picked: Vec<String> = (0..3).map(|_| state.next_api_key(&keys())).collect();
+    assert_eq!(picked, ["a", "c", "a"]);
+  }
+
+  #[test]
+  fn next_api_key_forgets_expired_cooldowns() {
+    let mut state = State::default();
+    state.api_key_cooldowns.insert("a".into(), Instant::now());
+    assert_eq!(state.next_api_key(&keys()), "a");
+    assert!(state.api_key_cooldowns.is_empty());
+  }
+
+  #[test]
+  fn next_api_key_uses_the_key_whose_cooldown_ends_first() {
+    let mut state = State::default();
+    let now = Instant::now();
+    for (key, secs) in [("a", 30), ("b", 10), ("c", 20)] {
+      state.api_key_cooldowns.insert(key.into(), now + Duration::from_secs(secs));
     }
+    assert_eq!(state.next_api_key(&keys()), "b");
+    assert_eq!(state.next_api_key(&keys()), "b");
   }
 }
@@ -0,0 +1,217 @@
+//! Conversation history: the messages a conversation starts with, and trimming.
+
+use std::{fs, ops::Range, path::Path};
+
+use gstreamer::{glib, ClockTime, ErrorMessage};
+
+use super::Settings;
+use crate::filter::openai_model::OpenaiChatCompletionMessage;
+
+/// Drops the oldest messages of `history` until it has at most max-history messages and
+/// serializes to at most max-history-bytes (limits of 0 are ignored). Pinned system messages and
+/// the `seed` messages are never dropped, nor counted against max-history, and the newest message
+/// is always kept. `seed` is moved along with the messages it covers. Returns the number of
+/// messages dropped.
+pub(super) fn trim_history(
+  history: &mut Vec<OpenaiChatCompletionMessage>,
+  seed: &mut Range<usize>,
+  settings: &Settings,
+) -> usize {
+  if settings.max_history == 0 && settings.max_history_bytes == 0 {
+    return 0;
+  }
+  let pinned = |index: usize, message: &OpenaiChatCompletionMessage| {
+    seed.contains(&index) || settings.pin_system_prompt && message.role == "system"
+  };
+  // Only serialized when there is a byte limit.
+  let sizes: Vec<usize> = history
+    .iter()
+    .map(|message| match settings.max_history_bytes {
+      0 => 0,
+      _ => serde_json::to_vec(message).map_or(0, |message| message.len()),
+    })
+    .collect();
+  let mut count = history
+    .iter()
+    .enumerate()
+    .filter(|(index, message)| !pinned(*index, message))
+    .count();
+  let mut bytes: usize = sizes.iter().sum();
+  let mut dropped = vec![false; history.len()];
+  for (index, message) in history.iter().enumerate().take(history.len().saturating_sub(1)) {
+    let too_many = settings.max_history > 0 && count > settings.max_history as usize;
+    let too_big = settings.max_history_bytes > 0 && bytes > settings.max_history_bytes as usize;
+    if !too_many && !too_big {
+      break;
+    }
+    if !pinned(index, message) {
+      dropped[index] = true;
+      count -= 1;
+      bytes -= sizes[index];
+    }
+  }
+  let dropped_count = dropped.iter().filter(|dropped| **dropped).count();
+  let dropped_before_seed = dropped[..seed.start].iter().filter(|dropped| **dropped).count();
+  *seed = seed.start - dropped_before_seed..seed.end - dropped_before_seed;
+  let mut dropped = dropped.into_iter();
+  history.retain(|_| !dropped.next().unwrap());
+  dropped_count
+}
+
+/// Reads the messages of preload-history-file.
+pub(super) fn read_history(path: &Path) -> Result<Vec<OpenaiChatCompletionMessage>, ErrorMessage> {
+  fs::read_to_string(path)
+    .map_err(|e| e.to_string())
+    .and_then(|history| serde_json::from_str(&history).map_err(|e| e.to_string()))
+    .map_err(|e| {
+      gstreamer::error_msg!(
+        gstreamer::ResourceError::Settings,
+        ["Invalid preload history file {}: {}", path.display(), e]
+      )
+    })
+}
+
+/// Splits the system prompt into system messages. A JSON array of strings gives one message per
+/// string; anything else is sent as a single message.
+pub(super) fn system_messages(system_prompt: &str) -> Vec<OpenaiChatCompletionMessage> {
+  let contents = serde_json::from_str::<Vec<String>>(system_prompt)
+    .unwrap_or_else(|_| vec![system_prompt.to_owned()]);
+  contents
+    .into_iter()
+    .map(|content| OpenaiChatCompletionMessage {
+      role: "system".into(),
+      content,
+      ..Default::default()
+    })
+    .collect()
+}
+
+/// Substitutes the placeholders of system-prompt and user-template: `{now}` with the local time in
+/// ISO 8601 format, `{pts}` with the PTS of the input buffer (or `none`), and `{turn}` with the
+/// number of the turn in the conversation, starting at 1.
+pub(super) fn expand_placeholders(text: &str, pts: Option<ClockTime>, turn: u64) -> String {
+  let mut text = text.to_owned();
+  if text.contains("{now}") {
+    let now = glib::DateTime::now_local()
+      .and_then(|now| now.format("%Y-%m-%dT%H:%M:%S%:z"))
+      .map(|now| now.to_string())
+      .unwrap_or_default();
+    text = text.replace("{now}", &now);
+  }
+  if text.contains("{pts}") {
+    let pts = pts.map_or_else(|| "none".to_owned(), |pts| pts.to_string());
+    text = text.replace("{pts}", &pts);
+  }
+  text.replace("{turn}", &turn.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(role: &str, content: &str) -> OpenaiChatCompletionMessage {
+    OpenaiChatCompletionMessage {
+      role: role.into(),
+      content: content.into(),
+      ..Default::default()
+    }
+  }
+
+  fn contents(history: &[OpenaiChatCompletionMessage]) -> Vec<&str> {
+    history.iter().map(|message| message.content.as_str()).collect()
+  }
+
+  #[test]
+  fn trim_history_without_limits_keeps_everything() {
+    let mut history = vec![message("user", "a"), message("assistant", "b")];
+    let mut seed = 0..0;
+    assert_eq!(trim_history(&mut history, &mut seed, &Settings::default()), 0);
+    assert_eq!(contents(&history), ["a", "b"]);
+  }
+
+  #[test]
+  fn trim_history_drops_the_oldest_messages() {
+    let mut history = vec![
+      message("system", "prompt"),
+      message("user", "a"),
+      message("assistant", "b"),
+      message("user", "c"),
+    ];
+    let mut seed = 0..0;
+    let settings = Settings {
+      max_history: 2,
+      pin_system_prompt: true,
+      ..Default::default()
+    };
+    assert_eq!(trim_history(&mut history, &mut seed, &settings), 1);
+    assert_eq!(contents(&history), ["prompt", "b", "c"]);
+  }
+
+  #[test]
+  fn trim_history_drops_system_messages_unless_pinned() {
+    let mut history = vec![message("system", "prompt"), message("user", "a")];
+    let mut seed = 0..0;
+    let settings = Settings {
+      max_history: 1,
+      pin_system_prompt: false,
+      ..Default::default()
+    };
+    assert_eq!(trim_history(&mut history, &mut seed, &settings), 1);
+    assert_eq!(contents(&history), ["a"]);
+  }
+
+  #[test]
+  fn trim_history_keeps_the_seed_and_moves_it() {
+    let mut history = vec![
+      message("user", "a"),
+      message("user", "example"),
+      message("assistant", "answer"),
+      message("user", "b"),
+      message("user", "c"),
+    ];
+    let mut seed = 1..3;
+    let settings = Settings {
+      max_history: 1,
+      ..Default::default()
+    };
+    assert_eq!(trim_history(&mut history, &mut seed, &settings), 2);
+    assert_eq!(contents(&history), ["example", "answer", "c"]);
+    assert_eq!(seed, 0..2);
+  }
+
+  #[test]
+  fn trim_history_limits_bytes_but_keeps_the_newest_message() {
+    let mut history = vec![message("user", "a"), message("user", &"b".repeat(100))];
+    let mut seed = 0..0;
+    let settings = Settings {
+      max_history_bytes: 10,
+      ..Default::default()
+    };
+    assert_eq!(trim_history(&mut history, &mut seed, &settings), 1);
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].content.len(), 100);
+  }
+
+  #[test]
+  fn system_messages_splits_json_arrays() {
+    assert_eq!(contents(&system_messages(r#"["a", "b"]"#)), ["a", "b"]);
+    assert_eq!(contents(&system_messages("[not json")), ["[not json"]);
+  }
+
+  #[test]
+  fn expand_placeholders_substitutes_pts_and_turn() {
+    assert_eq!(
+      expand_placeholders("{turn}: {pts}", Some(ClockTime::from_seconds(1)), 3),
+      "3: 0:00:01.000000000"
+    );
+    assert_eq!(expand_placeholders("{pts} {pts}", None, 1), "none none");
+    assert_eq!(expand_placeholders("{unknown}", None, 1), "{unknown}");
+  }
+
+  #[test]
+  fn expand_placeholders_substitutes_now() {
+    let now = expand_placeholders("{now}", None, 1);
+    assert!(!now.contains("{now}"));
+    assert_eq!(now.as_bytes()[10], b'T');
+  }
+}
@@ -0,0 +1,344 @@
+//! Pushing responses and their side outputs on the pads, and posting messages about them.
+
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use gstreamer::{
+  prelude::*, Buffer, Caps, ClockTime, Event, FlowError, FlowSuccess, Pad, Structure, TagList,
+  TagMergeMode,
+};
+
+use super::{request::RequestError, Settings, CAPS, CAT, JSON_CAPS};
+use crate::filter::{
+  openai_model::OpenAiChatCompletionResponseUsage, CodeLanguage, OpenaiChatFilter,
+};
+
+/// Output queued with queue-output, in the order it was pushed.
+pub(super) enum QueuedOutput {
+  Buffer(Buffer),
+  Event(Event),
+}
+
+/// Output of a request task: pushed on the src pad from the task, or queued for the streaming
+/// thread to push when queue-output is set. Nothing is pushed while the src pad is unlinked, so
+/// that the element can run for its side effects alone.
+#[derive(Clone)]
+pub(super) struct SrcOutput {
+  pub(super) pad: Pad,
+  pub(super) queue: Option<Arc<Mutex<VecDeque<QueuedOutput>>>>,
+}
+
+impl SrcOutput {
+  pub(super) fn push(&self, buffer: Buffer) -> Result<FlowSuccess, FlowError> {
+    match &self.queue {
+      _ if !self.pad.is_linked() => {
+        gstreamer::trace!(CAT, "src pad not linked, dropping output");
+        Ok(FlowSuccess::Ok)
+      },
+      Some(queue) => {
+        queue.lock().unwrap().push_back(QueuedOutput::Buffer(buffer));
+        Ok(FlowSuccess::Ok)
+      },
+      None => self.pad.push(buffer),
+    }
+  }
+
+  /// Pushes `buffer`, logging a failure instead of returning it, for output that the request task
+  /// has no use for once downstream refuses it. Returns whether it was pushed.
+  pub(super) fn push_logged(&self, buffer: Buffer, what: &str) -> bool {
+    match self.push(buffer) {
+      Ok(_) => true,
+      Err(e) => {
+        gstreamer::debug!(CAT, "failed to push {}: {}", what, e);
+        false
+      },
+    }
+  }
+
+  pub(super) fn push_event(&self, event: Event) -> bool {
+    match &self.queue {
+      Some(queue) => {
+        queue.lock().unwrap().push_back(QueuedOutput::Event(event));
+        true
+      },
+      None => self.pad.push_event(event),
+    }
+  }
+}
+
+/// Applies output-regex to a response.
+pub(super) fn replace_output(settings: &Settings, content: &str) -> String {
+  match &settings.output_regex {
+    Some(regex) => regex
+      .replace_all(content, &settings.output_replace)
+      .unwrap_or_else(|e| {
+        gstreamer::warning!(CAT, "failed to apply output-regex: {}", e);
+        content.to_owned()
+      }),
+    None => content.to_owned(),
+  }
+}
+
+/// Decodes the audio of a response and pushes it on the audio pad.
+pub(super) fn push_audio(element: &OpenaiChatFilter, settings: &Settings, data: &str) {
+  // The pad is added on start, so modalities may have been changed to text+audio since.
+  let audio_pad = match element.static_pad("audio") {
+    Some(audio_pad) => audio_pad,
+    None => {
+      gstreamer::debug!(CAT, "no audio pad, dropping the audio");
+      return;
+    },
+  };
+  let audio = match BASE64_STANDARD.decode(data) {
+    Ok(audio) => audio,
+    Err(e) => {
+      gstreamer::warning!(CAT, "failed to decode audio from OpenAI API: {}", e);
+      return;
+    },
+  };
+  let caps = settings.audio_format.caps();
+  if audio_pad.current_caps().as_ref() != Some(&caps) {
+    if audio_pad.sticky_event::<gstreamer::event::StreamStart>(0).is_none() {
+      let stream_id = format!("{}/audio", element.name());
+      audio_pad.push_event(gstreamer::event::StreamStart::new(&stream_id));
+    }
+    audio_pad.push_event(gstreamer::event::Caps::new(&caps));
+    if audio_pad.sticky_event::<gstreamer::event::Segment>(0).is_none() {
+      let segment = gstreamer::FormattedSegment::<ClockTime>::new();
+      audio_pad.push_event(gstreamer::event::Segment::new(&segment));
+    }
+  }
+  // The audio pad is optional, so it is fine for it to be unlinked.
+  if let Err(e) = audio_pad.push(Buffer::from_slice(audio)) {
+    gstreamer::debug!(CAT, "failed to push audio: {}", e);
+  }
+}
+
+/// Returns the caps of the src pad for `settings`, which is where every output format is
+/// negotiated: JSON with output-envelope, and text otherwise.
+pub(super) fn src_caps(settings: &Settings) -> &'static Caps {
+  if settings.output_envelope {
+    &JSON_CAPS
+  }
+  else {
+    &CAPS
+  }
+}
+
+/// Pushes the events starting the stream of an extra src pad, before its first buffer.
+pub(super) fn start_stream(element: &OpenaiChatFilter, pad: &Pad, caps: &Caps) {
+  if pad.sticky_event::<gstreamer::event::StreamStart>(0).is_none() {
+    let stream_id = format!("{}/{}", element.name(), pad.name());
+    pad.push_event(gstreamer::event::StreamStart::new(&stream_id));
+    pad.push_event(gstreamer::event::Caps::new(caps));
+    let segment = gstreamer::FormattedSegment::<ClockTime>::new();
+    pad.push_event(gstreamer::event::Segment::new(&segment));
+  }
+}
+
+/// Pushes the reasoning of a response on the reasoning pad, if it is linked.
+pub(super) fn push_reasoning(element: &OpenaiChatFilter, reasoning: &str) {
+  let reasoning_pad = match element.static_pad("reasoning") {
+    Some(reasoning_pad) if reasoning_pad.is_linked() => reasoning_pad,
+    _ => return,
+  };
+  start_stream(element, &reasoning_pad, &CAPS);
+  if let Err(e) = reasoning_pad.push(text_buffer(&format!("{}\n", reasoning))) {
+    gstreamer::debug!(CAT, "failed to push reasoning: {}", e);
+  }
+}
+
+/// Pushes a JSON object describing a failed request on the error pad, if it is linked.
+pub(super) fn push_error(element: &OpenaiChatFilter, error: &RequestError) {
+  let error_pad = match element.static_pad("error") {
+    Some(error_pad) if error_pad.is_linked() => error_pad,
+    _ => return,
+  };
+  start_stream(element, &error_pad, &JSON_CAPS);
+  let error = serde_json::json!({
+    "status": error.status.map(|status| status.as_u16()),
+    "message": error.message,
+    "request-id": error.request_id,
+  });
+  if let Err(e) = error_pad.push(text_buffer(&format!("{}\n", error))) {
+    gstreamer::debug!(CAT, "failed to push error: {}", e);
+  }
+}
+
+/// Pushes a code block on the code pad, preceded by a tag event with its language.
+pub(super) fn push_code(element: &OpenaiChatFilter, language: &str, code: &str) {
+  let code_pad = element.static_pad("code").unwrap();
+  start_stream(element, &code_pad, &CAPS);
+  let mut tags = TagList::new();
+  tags
+    .get_mut()
+    .unwrap()
+    .add::<CodeLanguage>(&language, TagMergeMode::Replace);
+  code_pad.push_event(gstreamer::event::Tag::new(tags));
+  if let Err(e) = code_pad.push(text_buffer(code)) {
+    gstreamer::debug!(CAT, "failed to push code: {}", e);
+  }
+}
+
+/// Takes the fenced code blocks out of a response, returning the rest of it and the blocks with
+/// their languages. A block left open runs to the end of the response.
+pub(super) fn split_code_blocks(text: &str) -> (String, Vec<(String, String)>) {
+  let mut prose = String::new();
+  let mut code_blocks = Vec::new();
+  let mut code_block: Option<(String, String)> = None;
+  for line in text.split_inclusive('\n') {
+    let fence = line.trim_start().strip_prefix("```");
+    match (&mut code_block, fence) {
+      (None, Some(language)) => code_block = Some((language.trim().to_owned(), String::new())),
+      (None, None) => prose.push_str(line),
+      (Some(_), Some(_)) => code_blocks.extend(code_block.take()),
+      (Some((_, code)), None) => code.push_str(line),
+    }
+  }
+  code_blocks.extend(code_block);
+  (prose, code_blocks)
+}
+
+/// Splits text after each sentence delimiter and the whitespace following it, so that the
+/// sentences concatenate back to the text. ASCII delimiters only end a sentence when followed by
+/// whitespace, so that e.g. 3.14 is not split; other delimiters, such as 。, always do.
+pub(super) fn split_sentences<'a>(text: &'a str, delimiters: &str) -> Vec<&'a str> {
+  let mut sentences = Vec::new();
+  let mut start = 0;
+  let mut chars = text.char_indices().peekable();
+  while let Some((_, c)) = chars.next() {
+    if !delimiters.contains(c) {
+      continue;
+    }
+    let next = chars.peek().map(|(_, next)| *next);
+    if next.is_some_and(|next| delimiters.contains(next)) {
+      continue;
+    }
+    if c.is_ascii() && next.is_some_and(|next| !next.is_whitespace()) {
+      continue;
+    }
+    while chars.next_if(|(_, next)| next.is_whitespace()).is_some() {}
+    let end = chars.peek().map_or(text.len(), |(index, _)| *index);
+    sentences.push(&text[start..end]);
+    start = end;
+  }
+  if start < text.len() {
+    sentences.push(&text[start..]);
+  }
+  sentences
+}
+
+pub(super) fn text_buffer(content: &str) -> Buffer {
+  let mut buffer = Buffer::with_size(content.len()).unwrap();
+  buffer
+    .get_mut()
+    .unwrap()
+    .copy_from_slice(0, content.as_bytes())
+    .unwrap();
+  buffer
+}
+
+pub(super) fn post_delta_message(element: &OpenaiChatFilter, settings: &Settings, delta: &str) {
+  let structure = Structure::builder("openaichat-delta")
+    .field("text", delta)
+    .field("request-tag", &settings.request_tag)
+    .build();
+  let message = gstreamer::message::Application::builder(structure)
+    .src(element)
+    .build();
+  if element.post_message(message).is_err() {
+    gstreamer::debug!(CAT, "failed to post delta message: no bus");
+  }
+}
+
+/// Estimates the cost of a response from cost-per-1k-prompt and cost-per-1k-completion.
+pub(super) fn estimated_cost(
+  settings: &Settings,
+  usage: &OpenAiChatCompletionResponseUsage,
+) -> f64 {
+  usage.prompt_tokens as f64 / 1000.0 * settings.cost_per_1k_prompt
+    + usage.completion_tokens as f64 / 1000.0 * settings.cost_per_1k_completion
+}
+
+pub(super) fn post_usage_message(
+  element: &OpenaiChatFilter,
+  settings: &Settings,
+  response_model: &str,
+  usage: &OpenAiChatCompletionResponseUsage,
+) {
+  let structure = Structure::builder("openaichat-usage")
+    .field("model", response_model)
+    .field("requested-model", &settings.model)
+    .field("prompt-tokens", usage.prompt_tokens)
+    .field("completion-tokens", usage.completion_tokens)
+    .field("total-tokens", usage.total_tokens)
+    .field("estimated-cost", estimated_cost(settings, usage))
+    .field("request-tag", &settings.request_tag)
+    .build();
+  let message = gstreamer::message::Application::builder(structure)
+    .src(element)
+    .build();
+  if element.post_message(message).is_err() {
+    gstreamer::debug!(CAT, "failed to post usage message: no bus");
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn split_sentences_concatenates_back_to_the_text() {
+    let text = "Hello there. How are you?  Fine!\nBye";
+    let sentences = split_sentences(text, ".?!");
+    assert_eq!(sentences, ["Hello there. ", "How are you?  ", "Fine!\n", "Bye"]);
+    assert_eq!(sentences.concat(), text);
+  }
+
+  #[test]
+  fn split_sentences_needs_whitespace_after_ascii_delimiters() {
+    assert_eq!(split_sentences("Pi is 3.14. Yes", "."), ["Pi is 3.14. ", "Yes"]);
+    assert_eq!(split_sentences("Really?! Yes.", "?!."), ["Really?! ", "Yes."]);
+  }
+
+  #[test]
+  fn split_sentences_always_splits_after_other_delimiters() {
+    assert_eq!(split_sentences("こんにちは。元気？", "。？"), ["こんにちは。", "元気？"]);
+  }
+
+  #[test]
+  fn split_sentences_of_empty_text() {
+    assert!(split_sentences("", ".").is_empty());
+  }
+
+  #[test]
+  fn split_code_blocks_separates_prose_and_code() {
+    let text = "Try this:\n```rust\nfn main() {}\n```\nand this:\n```\nls\n```\nDone.\n";
+    let (prose, code_blocks) = split_code_blocks(text);
+    assert_eq!(prose, "Try this:\nand this:\nDone.\n");
+    assert_eq!(
+      code_blocks,
+      [
+        ("rust".to_owned(), "fn main() {}\n".to_owned()),
+        (String::new(), "ls\n".to_owned()),
+      ]
+    );
+  }
+
+  #[test]
+  fn split_code_blocks_keeps_an_unterminated_block() {
+    let (prose, code_blocks) = split_code_blocks("Here:\n  ``` python \nprint(1)");
+    assert_eq!(prose, "Here:\n");
+    assert_eq!(code_blocks, [("python".to_owned(), "print(1)".to_owned())]);
+  }
+
+  #[test]
+  fn split_code_blocks_without_code() {
+    let (prose, code_blocks) = split_code_blocks("No code here.");
+    assert_eq!(prose, "No code here.");
+    assert!(code_blocks.is_empty());
+  }
+}
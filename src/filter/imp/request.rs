@@ -0,0 +1,1213 @@
+//! Sending requests: the request queue, and a request from its HTTP attempts to its output.
+
+use std::{
+  cmp,
+  collections::{hash_map::RandomState, BinaryHeap},
+  env, fmt, fs,
+  hash::{BuildHasher, Hasher},
+  iter, mem,
+  sync::{atomic::Ordering, Arc, Mutex},
+  time::{Duration, Instant},
+};
+
+use gstreamer::{
+  prelude::*, subclass::prelude::ObjectSubclassIsExt, Buffer, BufferFlags, ClockTime, Pad,
+  Structure, TagList, TagMergeMode,
+};
+use hyper::{
+  header::{HeaderValue, CONTENT_TYPE, HOST, RETRY_AFTER},
+  Body, Method, Request, Response, StatusCode, Uri,
+};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+  output::{
+    estimated_cost, post_delta_message, post_usage_message, push_audio, push_code, push_error,
+    push_reasoning, replace_output, split_code_blocks, split_sentences, text_buffer, SrcOutput,
+  },
+  NOutput, OnError, ReasoningOutput, RequestContentType, Settings, State, Stats, StreamMode, CAT,
+  CONVERSATION_TAG_KEY, DEFAULT_API_KEY_COOLDOWN, LAST_RESPONSE_MAX_LEN,
+  OPENAI_MODERATION_ENDPOINT, OPENAI_RESPONSES_ENDPOINT, OUTPUT_CHECK_INTERVAL,
+};
+use crate::filter::{
+  client::{self, read_body, HttpsClient, ReadError, OPENAI_ENDPOINT},
+  openai_model::{
+    OpenAiChatCompletionResponse, OpenaiChatCompletionMessage, OpenaiModerationRequest,
+    OpenaiModerationResponse, OpenaiResponsesResponse,
+  },
+  sse,
+  transport::ChatTransport,
+  FinishReason, OpenaiChatFilter, Provider,
+};
+
+/// The request being sent by the request task.
+pub(super) struct InFlight {
+  pub(super) priority: i32,
+  /// Cancels this attempt only, not the request.
+  pub(super) cancellation: CancellationToken,
+  pub(super) preempted: bool,
+}
+
+/// A request waiting for the request task, ordered by priority, then first queued first.
+pub(super) struct QueuedRequest {
+  pub(super) sequence: u64,
+  pub(super) request: PendingRequest,
+}
+
+impl Ord for QueuedRequest {
+  fn cmp(&self, other: &Self) -> cmp::Ordering {
+    self
+      .request
+      .priority
+      .cmp(&other.request.priority)
+      .then_with(|| other.sequence.cmp(&self.sequence))
+  }
+}
+
+impl PartialOrd for QueuedRequest {
+  fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for QueuedRequest {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == cmp::Ordering::Equal
+  }
+}
+
+impl Eq for QueuedRequest {}
+
+/// Body of the request task: sends the requests one at a time, highest priority first. Up to
+/// `max_queued` requests are taken off the channel to be ordered. A preempted request is queued
+/// again, and sent once no request with a higher priority is waiting.
+pub(super) async fn send_requests(
+  mut receiver: mpsc::Receiver<PendingRequest>,
+  max_queued: usize,
+  in_flight: Arc<Mutex<Option<InFlight>>>,
+) {
+  let mut queue = BinaryHeap::new();
+  let mut sequence = 0;
+  loop {
+    if queue.is_empty() {
+      match receiver.recv().await {
+        Some(request) => queue.push(QueuedRequest { sequence, request }),
+        None => break,
+      }
+      sequence += 1;
+    }
+    while queue.len() < max_queued.max(1) {
+      match receiver.try_recv() {
+        Ok(request) => queue.push(QueuedRequest { sequence, request }),
+        Err(_) => break,
+      }
+      sequence += 1;
+    }
+    let QueuedRequest { sequence: request_sequence, request } = queue.pop().unwrap();
+    let mut attempt = request.clone();
+    attempt.cancellation = request.cancellation.child_token();
+    *in_flight.lock().unwrap() = Some(InFlight {
+      priority: request.priority,
+      cancellation: attempt.cancellation.clone(),
+      preempted: false,
+    });
+    PendingRequest::run(attempt).await;
+    let preempted = in_flight.lock().unwrap().take().is_some_and(|in_flight| in_flight.preempted);
+    if preempted && !request.cancellation.is_cancelled() {
+      queue.push(QueuedRequest {
+        sequence: request_sequence,
+        request,
+      });
+    }
+  }
+}
+
+/// Why a request failed, with the status and request ID of the response if there was one.
+pub(super) struct RequestError {
+  pub(super) message: String,
+  pub(super) status: Option<StatusCode>,
+  pub(super) request_id: Option<String>,
+}
+
+impl RequestError {
+  pub(super) fn new(message: String) -> Self {
+    Self {
+      message,
+      status: None,
+      request_id: None,
+    }
+  }
+}
+
+impl fmt::Display for RequestError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.message)
+  }
+}
+
+/// Everything needed to send a request and push its response from the runtime.
+#[derive(Clone)]
+pub(super) struct PendingRequest {
+  pub(super) element: OpenaiChatFilter,
+  pub(super) cancellation: CancellationToken,
+  pub(super) endpoint: Uri,
+  pub(super) src_pad: SrcOutput,
+  pub(super) state: Arc<Mutex<State>>,
+  pub(super) stats: Arc<Stats>,
+  pub(super) client: Arc<dyn ChatTransport>,
+  pub(super) settings: Settings,
+  pub(super) api_key: String,
+  pub(super) conversation_id: String,
+  pub(super) request_body: serde_json::Value,
+  pub(super) input_pts: Option<ClockTime>,
+  pub(super) input_duration: Option<ClockTime>,
+  /// Input pushed ahead of the response when echo-input is set.
+  pub(super) echo: Option<String>,
+  /// Input pushed in place of the response if the request fails, when on-error is passthrough.
+  pub(super) passthrough: Option<String>,
+  /// Queued requests are sent highest priority first.
+  pub(super) priority: i32,
+}
+
+impl PendingRequest {
+  /// Sends the request and pushes the response. A failed request is handled as set by on-error.
+  /// If nothing is pushed, a gap event covering the input buffer is pushed instead when
+  /// `emit-gaps` is set, so that downstream live elements do not stall.
+  async fn run(mut self) {
+    let src_pad = self.src_pad.clone();
+    // Pushed here rather than in generate_output, so that they stay next to their response while
+    // earlier requests are still queued.
+    if let Some(echo) = self.echo.take() {
+      src_pad.push_logged(text_buffer(&echo), "input");
+    }
+    if !self.settings.thinking_placeholder.is_empty() {
+      src_pad.push_logged(text_buffer(&self.settings.thinking_placeholder), "placeholder");
+    }
+    let cancellation = self.cancellation.clone();
+    let element = self.element.clone();
+    let on_error = self.settings.on_error;
+    let passthrough = self.passthrough.take();
+    let gap = self
+      .input_pts
+      .filter(|_| self.settings.emit_gaps)
+      .map(|pts| gstreamer::event::Gap::builder(pts).duration(self.input_duration).build());
+    // Nobody would get the response once the src pad is flushing, so it is not worth paying for.
+    let watch = cancellation.child_token();
+    if output_unused(&src_pad.pad, self.settings.require_src) {
+      gstreamer::debug!(CAT, "src pad unusable, not sending the request");
+      cancellation.cancel();
+    }
+    else {
+      tokio::spawn(abort_when_output_unused(
+        src_pad.pad.clone(),
+        self.settings.require_src,
+        cancellation.clone(),
+        watch.clone(),
+      ));
+    }
+    #[cfg(feature = "tracing")]
+    let result = {
+      use tracing::Instrument;
+      let span = request_span(&self.settings, &self.conversation_id);
+      let started = Instant::now();
+      let result = self.send().instrument(span.clone()).await;
+      span.record("latency_ms", started.elapsed().as_millis() as u64);
+      result
+    };
+    #[cfg(not(feature = "tracing"))]
+    let result = self.send().await;
+    watch.cancel();
+    element.imp().count_failure(result.is_err());
+    if let Err(e) = &result {
+      push_error(&element, e);
+    }
+    let pushed = match result {
+      Ok(pushed) => pushed,
+      Err(e) => match (on_error, passthrough) {
+        (OnError::Error, _) => {
+          gstreamer::element_error!(element, gstreamer::ResourceError::Failed, ["{}", e]);
+          false
+        },
+        (OnError::Passthrough, Some(passthrough)) => {
+          gstreamer::debug!(CAT, "{}, pushing the input instead", e);
+          src_pad.push(text_buffer(&passthrough)).is_ok()
+        },
+        _ => {
+          gstreamer::debug!(CAT, "{}", e);
+          false
+        },
+      },
+    };
+    if !pushed && !cancellation.is_cancelled() {
+      if let Some(gap) = gap {
+        gstreamer::debug!(CAT, "no output for request, pushing gap event");
+        src_pad.push_event(gap);
+      }
+    }
+  }
+
+  /// Returns whether the response was pushed, or why the request failed.
+  async fn send(self) -> Result<bool, RequestError> {
+    let PendingRequest {
+      element,
+      cancellation,
+      endpoint,
+      src_pad,
+      state,
+      stats,
+      client,
+      settings,
+      api_key,
+      conversation_id,
+      mut request_body,
+      input_pts,
+      ..
+    } = self;
+    gstreamer::trace!(CAT, "request body: {}", display_json(&settings, &request_body));
+    let mut request_bytes = serde_json::to_vec(&request_body).unwrap();
+    // Streaming is only implemented for chat completions.
+    let mut stream = (settings.stream || settings.flush_after_ms > 0) && !settings.responses_api;
+    // Moderation has to see the whole response before any of it is pushed.
+    // Envelopes are only built from the whole response.
+    let mut push_deltas = stream && !settings.moderate_output && !settings.output_envelope;
+    // With flush-after-ms, deltas are held back until the time is up.
+    let hold_deltas = !settings.stream;
+    let flush_after = Duration::from_millis(settings.flush_after_ms.into());
+    let mut held;
+    // Backspaces erasing the thinking placeholder, pushed before the response.
+    let erase = if settings.erase_placeholder {
+      "\u{8}".repeat(settings.thinking_placeholder.chars().count())
+    }
+    else {
+      String::new()
+    };
+    let mut prefix_pushed;
+    let mut streamed;
+    let mut request_id;
+    let mut first_token_ms;
+    let mut retries = 0;
+    let (mut response_body, choice_index) = loop {
+      let delay = pacing_delay(&settings, &mut state.lock().unwrap());
+      if !delay.is_zero() {
+        gstreamer::debug!(CAT, "waiting {:?} before sending the request", delay);
+        if cancellation.run_until_cancelled(tokio::time::sleep(delay)).await.is_none() {
+          gstreamer::debug!(CAT, "request cancelled");
+          return Ok(false);
+        }
+      }
+      let request = build_request(
+        settings.request_method.clone(),
+        endpoint.clone(),
+        &settings,
+        &api_key,
+        request_bytes.clone(),
+      );
+      let dispatched = Instant::now();
+      let response = match cancellation
+        .run_until_cancelled(send_request(client.as_ref(), &settings, request))
+        .await
+      {
+        Some(Ok(response)) => response,
+        None => {
+          gstreamer::debug!(CAT, "request cancelled");
+          return Ok(false);
+        },
+        Some(Err(e)) => {
+          return Err(RequestError::new(format!("request to OpenAI API failed: {}", e)));
+        },
+      };
+      request_id = response
+        .headers()
+        .get("x-request-id")
+        .and_then(|request_id| request_id.to_str().ok())
+        .map(ToOwned::to_owned);
+      gstreamer::debug!(
+        CAT,
+        "response {} with request ID {}",
+        response.status(),
+        request_id.as_deref().unwrap_or("<none>")
+      );
+      state.lock().unwrap().last_request_id = request_id.clone();
+      if response.status() == StatusCode::TOO_MANY_REQUESTS && !settings.api_keys.is_empty() {
+        let cooldown = response
+          .headers()
+          .get(RETRY_AFTER)
+          .and_then(|retry_after| retry_after.to_str().ok())
+          .and_then(|retry_after| retry_after.parse().ok())
+          .map(Duration::from_secs)
+          .unwrap_or(DEFAULT_API_KEY_COOLDOWN);
+        gstreamer::debug!(CAT, "API key rate limited, not using it for {:?}", cooldown);
+        state
+          .lock()
+          .unwrap()
+          .api_key_cooldowns
+          .insert(api_key.clone(), Instant::now() + cooldown);
+      }
+      let status = response.status();
+      #[cfg(feature = "tracing")]
+      tracing::Span::current().record("status", status.as_u16());
+      if status == StatusCode::BAD_REQUEST && stream && settings.stream_fallback {
+        let error = match cancellation
+          .run_until_cancelled(read_body(
+            response.into_body(),
+            settings.max_response_bytes as usize,
+            |_| {},
+          ))
+          .await
+        {
+          Some(error) => error.unwrap_or_default(),
+          None => {
+            gstreamer::debug!(CAT, "request cancelled");
+            return Ok(false);
+          },
+        };
+        if !String::from_utf8_lossy(&error).to_lowercase().contains("stream") {
+          return Err(RequestError {
+            status: Some(status),
+            request_id,
+            ..RequestError::new(format!("HTTP error from OpenAI API: {}", status))
+          });
+        }
+        gstreamer::info!(CAT, "streaming rejected by the server, sending the request without it");
+        if let Some(request_body) = request_body.as_object_mut() {
+          request_body.remove("stream");
+          request_body.remove("stream_options");
+        }
+        request_bytes = serde_json::to_vec(&request_body).unwrap();
+        stream = false;
+        push_deltas = false;
+        continue;
+      }
+      if !status.is_success() {
+        return Err(RequestError {
+          status: Some(status),
+          request_id,
+          ..RequestError::new(format!("HTTP error from OpenAI API: {}", status))
+        });
+      }
+      let framing =
+        client::response_framing(&response).or(stream.then_some(sse::Framing::EventStream));
+      prefix_pushed = false;
+      streamed = String::new();
+      held = String::new();
+      first_token_ms = None;
+      stats.last_completion_tokens.store(0, Ordering::Relaxed);
+      let mut delta_parser =
+        sse::DeltaParser::new(settings.provider.stream_format(), framing.unwrap_or_default());
+      let response_body = cancellation.run_until_cancelled(read_body(
+        response.into_body(),
+        settings.max_response_bytes as usize,
+        |body| {
+          if !stream {
+            return;
+          }
+          delta_parser.parse(body, |delta| {
+            first_token_ms.get_or_insert_with(|| dispatched.elapsed().as_millis() as u64);
+            stats.last_completion_tokens.fetch_add(1, Ordering::Relaxed);
+            if push_deltas && hold_deltas && dispatched.elapsed() < flush_after {
+              held.push_str(delta);
+            }
+            else if push_deltas {
+              // Everything held back goes out with the first delta after flush-after-ms.
+              held.push_str(delta);
+              let delta = mem::take(&mut held);
+              let output = match settings.stream_mode {
+                StreamMode::Delta if prefix_pushed => delta,
+                StreamMode::Delta => {
+                  prefix_pushed = true;
+                  format!("{}{}{}", erase, settings.output_role_prefix, delta)
+                },
+                StreamMode::Cumulative => {
+                  prefix_pushed = true;
+                  streamed.push_str(&delta);
+                  format!("{}{}", settings.output_role_prefix, streamed)
+                },
+              };
+              src_pad.push_logged(text_buffer(&output), "delta");
+            }
+            if settings.stream_bus_messages {
+              post_delta_message(&element, &settings, delta);
+            }
+          });
+        },
+      ))
+      .await;
+      // Nothing of a cancelled response is pushed further or added to the history.
+      let response_body = match response_body {
+        None => {
+          gstreamer::debug!(CAT, "request cancelled while reading the response");
+          return Ok(false);
+        },
+        Some(response_body) => response_body,
+      };
+      let response_body = match response_body {
+        Ok(response_body) => response_body,
+        Err(ReadError::TooLarge) => {
+          gstreamer::element_error!(
+            element,
+            gstreamer::ResourceError::Read,
+            [
+              "Response from OpenAI API exceeded max-response-bytes ({})",
+              settings.max_response_bytes
+            ]
+          );
+          return Ok(false);
+        },
+        Err(ReadError::Http(e)) => {
+          return Err(RequestError {
+            request_id,
+            ..RequestError::new(format!("failed to read response from OpenAI API: {}", e))
+          });
+        },
+      };
+      state.lock().unwrap().last_response = Some(truncated_response(&response_body));
+      let response_body = if settings.responses_api {
+        serde_json::from_slice::<OpenaiResponsesResponse>(&response_body).map(Into::into)
+      }
+      else {
+        parse_response(&settings, framing, &response_body)
+      };
+      let mut response_body = match response_body {
+        Ok(response_body) => response_body,
+        Err(e) => {
+          return Err(RequestError {
+            request_id,
+            ..RequestError::new(format!("failed to parse response from OpenAI API: {}", e))
+          });
+        },
+      };
+      for choice in &mut response_body.choices {
+        if let Some(audio) = &choice.message.audio {
+          if choice.message.content.is_empty() {
+            choice.message.content = audio.transcript.clone();
+          }
+        }
+      }
+      if response_body.choices.is_empty() {
+        return Err(RequestError {
+          request_id,
+          ..RequestError::new("response from OpenAI API has no choices".to_owned())
+        });
+      }
+      let choice_index = if (settings.choice_index as usize) < response_body.choices.len() {
+        settings.choice_index as usize
+      }
+      else {
+        gstreamer::warning!(
+          CAT,
+          "choice-index {} is out of range for {} choices, using 0",
+          settings.choice_index,
+          response_body.choices.len()
+        );
+        0
+      };
+      if settings.retry_on_empty
+        && retries < settings.max_retries
+        && response_body.choices[choice_index].message.content.trim().is_empty()
+      {
+        retries += 1;
+        gstreamer::debug!(
+          CAT,
+          "empty response from OpenAI API, retrying ({}/{})",
+          retries,
+          settings.max_retries
+        );
+        continue;
+      }
+      break (response_body, choice_index);
+    };
+    if settings.moderate_output {
+      moderate_choices(&element, client.as_ref(), &settings, &api_key, &mut response_body).await;
+    }
+    let filtered = emit_filtered(&element, &settings, &response_body);
+    let response_model = response_body.model.as_deref().unwrap_or(&settings.model);
+    if response_model != settings.model {
+      gstreamer::warning!(
+        CAT,
+        "requested model {} but the response was generated by {}",
+        settings.model,
+        response_model
+      );
+    }
+    if settings.emit_usage_messages {
+      if let Some(usage) = &response_body.usage {
+        post_usage_message(&element, &settings, response_model, usage);
+      }
+    }
+    if let Some(usage) = &response_body.usage {
+      stats.last_prompt_tokens.store(usage.prompt_tokens, Ordering::Relaxed);
+      stats.last_completion_tokens.store(usage.completion_tokens, Ordering::Relaxed);
+      element.imp().add_cost(&settings, estimated_cost(&settings, usage));
+    }
+    for choice in &mut response_body.choices {
+      let transformed = element
+        .emit_by_name::<Option<String>>("transform-response", &[&choice.message.content]);
+      if let Some(content) = transformed {
+        choice.message.content = content;
+      }
+    }
+    if settings.output_regex_history {
+      for choice in &mut response_body.choices {
+        choice.message.content = replace_output(&settings, &choice.message.content);
+      }
+    }
+    let message = &response_body.choices[choice_index].message;
+    let mut max_turns_reached = false;
+    let mut conversation_json = None;
+    let duplicate;
+    {
+      let mut state = state.lock().unwrap();
+      duplicate = settings.dedupe_responses
+        && state.last_content.as_deref() == Some(message.content.as_str());
+      if duplicate {
+        gstreamer::debug!(CAT, "suppressing duplicate response {:?}", message.content);
+      }
+      state.last_content = Some(message.content.clone());
+      // The conversation may have been evicted or reset while the request was in flight.
+      if let Some(conversation) = state.conversations.get_mut(&conversation_id) {
+        conversation.turn_count += 1;
+        if settings.responses_api {
+          conversation.previous_response_id = Some(response_body.id.clone());
+        }
+        if settings.store_responses && (!duplicate || settings.store_duplicates) {
+          // The transcript stands in for the audio in later requests.
+          conversation.history.push(OpenaiChatCompletionMessage {
+            role: message.role.clone(),
+            content: message.content.clone(),
+            name: settings.assistant_name.clone(),
+            ..Default::default()
+          });
+        }
+        max_turns_reached =
+          settings.max_turns > 0 && conversation.turn_count >= u64::from(settings.max_turns);
+        if settings.conversation_tags {
+          conversation_json = Some(serde_json::to_string(&conversation.history).unwrap());
+        }
+      }
+      if max_turns_reached {
+        state.conversations.remove(&conversation_id);
+      }
+      stats.update(&state);
+      state.last_system_fingerprint = response_body.system_fingerprint.clone();
+    }
+    if let Some(first_token_ms) = first_token_ms {
+      stats.last_first_token_ms.store(first_token_ms, Ordering::Relaxed);
+    }
+    let response_info = response_info(
+      &settings,
+      &response_body,
+      choice_index,
+      response_model,
+      request_id.as_deref(),
+      first_token_ms,
+    );
+    let reasoning = &message.reasoning_content;
+    element.emit_by_name::<()>("response-received", &[&response_info]);
+    if settings.reasoning_output == ReasoningOutput::Pad && !reasoning.is_empty() {
+      push_reasoning(&element, reasoning);
+    }
+    let finish_reason = &response_body.choices[choice_index].finish_reason;
+    if !finish_reason.is_empty() {
+      let mut tags = TagList::new();
+      tags
+        .get_mut()
+        .unwrap()
+        .add::<FinishReason>(&finish_reason.as_str(), TagMergeMode::Replace);
+      src_pad.push_event(gstreamer::event::Tag::new(tags));
+    }
+    // A response that arrived within flush-after-ms is pushed whole.
+    let push_deltas = push_deltas && (!hold_deltas || prefix_pushed);
+    let pushed = if push_deltas {
+      let end = match settings.stream_mode {
+        StreamMode::Delta if prefix_pushed => "\n".to_owned(),
+        StreamMode::Delta => format!("{}{}\n", erase, settings.output_role_prefix),
+        StreamMode::Cumulative => format!("{}{}\n", settings.output_role_prefix, streamed),
+      };
+      let pushed = src_pad.push_logged(text_buffer(&end), "response");
+      if settings.stream_emit_eos_marker {
+        let mut marker = Buffer::new();
+        marker.get_mut().unwrap().set_flags(BufferFlags::MARKER);
+        src_pad.push_logged(marker, "marker");
+      }
+      pushed
+    }
+    else if duplicate {
+      false
+    }
+    else {
+      push_response(
+        &element,
+        &src_pad,
+        &settings,
+        &response_body,
+        choice_index,
+        &filtered,
+        &erase,
+        input_pts,
+      )
+    };
+    if let Some(audio) = &message.audio {
+      push_audio(&element, &settings, &audio.data);
+    }
+    if let Some(conversation_json) = conversation_json {
+      let mut tags = TagList::new();
+      tags.get_mut().unwrap().add::<gstreamer::tags::ExtendedComment>(
+        &format!("{}={}", CONVERSATION_TAG_KEY, conversation_json).as_str(),
+        TagMergeMode::Replace,
+      );
+      src_pad.push_event(gstreamer::event::Tag::new(tags));
+    }
+    if max_turns_reached {
+      if !settings.closing_message.is_empty() {
+        let closing_message = text_buffer(&format!("{}\n", settings.closing_message));
+        src_pad.push_logged(closing_message, "closing message");
+      }
+      element.emit_by_name::<()>("conversation-ended", &[]);
+    }
+    Ok(pushed)
+  }
+}
+
+/// Replaces the choices flagged by the moderation endpoint, emitting `response-blocked` for each.
+async fn moderate_choices(
+  element: &OpenaiChatFilter,
+  client: &dyn ChatTransport,
+  settings: &Settings,
+  api_key: &str,
+  response_body: &mut OpenAiChatCompletionResponse,
+) {
+  let contents = response_body
+    .choices
+    .iter()
+    .map(|choice| choice.message.content.clone())
+    .collect();
+  let flagged = match moderate(client, settings, api_key, contents).await {
+    Ok(flagged) => flagged,
+    Err(e) => {
+      // Fail closed: a response that could not be checked is not pushed.
+      gstreamer::warning!(CAT, "failed to moderate response: {}", e);
+      vec![true; response_body.choices.len()]
+    },
+  };
+  // Fail closed as well for choices the moderation response has no result for.
+  let flagged = flagged.into_iter().chain(iter::repeat(true));
+  for (choice, flagged) in response_body.choices.iter_mut().zip(flagged) {
+    if flagged {
+      let content = mem::replace(
+        &mut choice.message.content,
+        settings.moderation_replacement.clone(),
+      );
+      let blocked_info = Structure::builder("openaichat-blocked")
+        .field("content", content)
+        .field("request-tag", &settings.request_tag)
+        .build();
+      element.emit_by_name::<()>("response-blocked", &[&blocked_info]);
+    }
+  }
+}
+
+/// Emits `response-filtered` for the choices stopped by the provider's content filter, whose
+/// content may be partial, returning which choices those are.
+fn emit_filtered(
+  element: &OpenaiChatFilter,
+  settings: &Settings,
+  response_body: &OpenAiChatCompletionResponse,
+) -> Vec<bool> {
+  let filtered: Vec<bool> = response_body
+    .choices
+    .iter()
+    .map(|choice| choice.finish_reason == "content_filter")
+    .collect();
+  for (choice, _) in response_body.choices.iter().zip(&filtered).filter(|(_, f)| **f) {
+    let filtered_info = Structure::builder("openaichat-filtered")
+      .field("content", &choice.message.content)
+      .field("request-tag", &settings.request_tag)
+      .build();
+    element.emit_by_name::<()>("response-filtered", &[&filtered_info]);
+  }
+  filtered
+}
+
+/// Describes a response for the `response-received` signal.
+fn response_info(
+  settings: &Settings,
+  response_body: &OpenAiChatCompletionResponse,
+  choice_index: usize,
+  response_model: &str,
+  request_id: Option<&str>,
+  first_token_ms: Option<u64>,
+) -> Structure {
+  let choice = &response_body.choices[choice_index];
+  let mut response_info = Structure::builder("openaichat-response")
+    .field("content", &choice.message.content)
+    .field("model", response_model)
+    .field("request-tag", &settings.request_tag);
+  if let Some(system_fingerprint) = &response_body.system_fingerprint {
+    response_info = response_info.field("system-fingerprint", system_fingerprint);
+  }
+  if let Some(request_id) = request_id {
+    response_info = response_info.field("request-id", request_id);
+  }
+  if let Some(logprobs) = &choice.logprobs {
+    response_info = response_info.field("logprobs", serde_json::to_string(logprobs).unwrap());
+  }
+  if let Some(first_token_ms) = first_token_ms {
+    response_info = response_info.field("first-token-ms", first_token_ms);
+  }
+  let reasoning = &choice.message.reasoning_content;
+  if settings.reasoning_output == ReasoningOutput::Signal && !reasoning.is_empty() {
+    response_info = response_info.field("reasoning", reasoning);
+  }
+  response_info.build()
+}
+
+/// Pushes a response that was not streamed, returning whether all of it was pushed.
+#[allow(clippy::too_many_arguments)]
+fn push_response(
+  element: &OpenaiChatFilter,
+  src_pad: &SrcOutput,
+  settings: &Settings,
+  response_body: &OpenAiChatCompletionResponse,
+  choice_index: usize,
+  filtered: &[bool],
+  erase: &str,
+  input_pts: Option<ClockTime>,
+) -> bool {
+  let response_model = response_body.model.as_deref().unwrap_or(&settings.model);
+  let contents: Vec<(usize, String)> = response_body
+    .choices
+    .iter()
+    .enumerate()
+    .zip(filtered)
+    .filter(|(_, filtered)| !(settings.suppress_filtered && **filtered))
+    .map(|((index, choice), _)| {
+      let replaced;
+      let content = if settings.output_regex_history {
+        &choice.message.content
+      }
+      else {
+        replaced = replace_output(settings, &choice.message.content);
+        &replaced
+      };
+      let content = if settings.output_envelope {
+        let envelope = serde_json::json!({
+          "content": content,
+          "model": response_model,
+          "finish_reason": choice.finish_reason,
+          "usage": response_body.usage,
+          "pts": input_pts.map(ClockTime::nseconds),
+          "logprobs": choice.logprobs,
+        });
+        format!("{}\n", envelope)
+      }
+      else {
+        format!("{}{}\n", settings.output_role_prefix, content)
+      };
+      (index, content)
+    })
+    .collect();
+  let outputs: Vec<String> = match settings.n_output {
+    NOutput::First => contents
+      .into_iter()
+      .filter(|(index, _)| *index == choice_index)
+      .map(|(_, content)| content)
+      .collect(),
+    NOutput::Concat if !contents.is_empty() => {
+      vec![contents.into_iter().map(|(_, content)| content).collect()]
+    },
+    NOutput::Concat => vec![],
+    NOutput::Separate => contents.into_iter().map(|(_, content)| content).collect(),
+  };
+  let mut pushed = !outputs.is_empty();
+  if pushed && !erase.is_empty() {
+    src_pad.push_logged(text_buffer(erase), "placeholder erasure");
+  }
+  // Code blocks are only taken out of the response when something consumes them.
+  let split_code = !settings.output_envelope
+    && element.static_pad("code").is_some_and(|code_pad| code_pad.is_linked());
+  for content in outputs {
+    let content = if split_code {
+      let (prose, code_blocks) = split_code_blocks(&content);
+      for (language, code) in code_blocks {
+        push_code(element, &language, &code);
+      }
+      prose
+    }
+    else {
+      content
+    };
+    if settings.split_sentences && !settings.output_envelope {
+      for sentence in split_sentences(&content, &settings.sentence_delimiters) {
+        pushed &= src_pad.push_logged(text_buffer(sentence), "response");
+      }
+    }
+    else {
+      pushed &= src_pad.push_logged(text_buffer(&content), "response");
+    }
+  }
+  pushed
+}
+
+/// Returns a temperature drawn uniformly from `temperature ± jitter`, clamped to the valid range.
+pub(super) fn jittered_temperature(temperature: f64, jitter: f64) -> f64 {
+  // Every RandomState is randomly keyed, which is enough randomness for sampling variety.
+  let random = RandomState::new().build_hasher().finish();
+  let unit = (random >> 11) as f64 / (1u64 << 53) as f64;
+  (temperature + jitter * (2.0 * unit - 1.0)).clamp(0.0, 2.0)
+}
+
+/// Resolves the API key, reading it from the token file if one is configured.
+pub(super) fn api_key(settings: &Settings) -> Result<String, String> {
+  match &settings.token_file {
+    Some(path) => fs::read_to_string(path)
+      .map(|token| token.trim().to_owned())
+      .map_err(|e| format!("Failed to read token file {}: {}", path.display(), e)),
+    None => env::var("OPENAI_API_KEY")
+      .map_err(|e| format!("Failed to read the OPENAI_API_KEY environment variable: {}", e)),
+  }
+}
+
+/// Serializes the request body and merges extra-body into it.
+pub(super) fn request_json(
+  settings: &Settings,
+  request: &impl serde::Serialize,
+) -> serde_json::Value {
+  let mut body = serde_json::to_value(request).unwrap();
+  if let serde_json::Value::Object(fields) = &mut body {
+    for (key, value) in &settings.extra_body {
+      if fields.contains_key(key) {
+        gstreamer::warning!(CAT, "extra-body overrides request field {}", key);
+      }
+      fields.insert(key.clone(), value.clone());
+    }
+    // Tools are only configured through extra-body, and the API rejects parallel_tool_calls
+    // without them. An explicit parallel_tool_calls in extra-body wins.
+    let has_tools = fields
+      .get("tools")
+      .and_then(|tools| tools.as_array())
+      .is_some_and(|tools| !tools.is_empty());
+    if has_tools {
+      fields
+        .entry("parallel_tool_calls")
+        .or_insert(settings.parallel_tool_calls.into());
+    }
+  }
+  body
+}
+
+/// Formats a request body for logs and dry-run output, which pretty-request makes readable.
+pub(super) fn display_json(settings: &Settings, body: &serde_json::Value) -> String {
+  if settings.pretty_request {
+    serde_json::to_string_pretty(body).unwrap()
+  }
+  else {
+    body.to_string()
+  }
+}
+
+/// Returns the URL of the chat completions endpoint. Azure OpenAI routes requests by deployment
+/// rather than by model, and an explicit endpoint is trusted as is.
+pub(super) fn chat_endpoint(settings: &Settings) -> String {
+  let base_url = settings.base_url.trim_end_matches('/');
+  if !settings.endpoint.is_empty() {
+    settings.endpoint.clone()
+  }
+  else if settings.responses_api {
+    responses_endpoint(settings)
+  }
+  else if !settings.deployment.is_empty() {
+    format!(
+      "{}/openai/deployments/{}/chat/completions?api-version={}",
+      base_url, settings.deployment, settings.api_version
+    )
+  }
+  else if !base_url.is_empty() {
+    format!("{}/chat/completions", base_url)
+  }
+  else if let Some(endpoint) = settings.provider.default_endpoint() {
+    endpoint.to_owned()
+  }
+  else {
+    OPENAI_ENDPOINT.clone()
+  }
+}
+
+pub(super) fn responses_endpoint(settings: &Settings) -> String {
+  let base_url = settings.base_url.trim_end_matches('/');
+  if !settings.deployment.is_empty() {
+    format!("{}/openai/responses?api-version={}", base_url, settings.api_version)
+  }
+  else if !base_url.is_empty() {
+    format!("{}/responses", base_url)
+  }
+  else {
+    OPENAI_RESPONSES_ENDPOINT.clone()
+  }
+}
+
+pub(super) fn moderation_endpoint(settings: &Settings) -> String {
+  let base_url = settings.base_url.trim_end_matches('/');
+  if !settings.deployment.is_empty() {
+    format!("{}/openai/moderations?api-version={}", base_url, settings.api_version)
+  }
+  else if !base_url.is_empty() {
+    format!("{}/moderations", base_url)
+  }
+  else {
+    OPENAI_MODERATION_ENDPOINT.clone()
+  }
+}
+
+pub(super) fn build_request(
+  method: Method,
+  uri: Uri,
+  settings: &Settings,
+  api_key: &str,
+  body: Vec<u8>,
+) -> Request<Body> {
+  // A dry run has no key, and local servers may not need one.
+  let auth_headers = if api_key.is_empty() {
+    vec![]
+  }
+  else {
+    let mut auth_headers: Vec<(&str, String)> = settings.provider.auth_headers(api_key);
+    if !settings.auth_header_name.is_empty() {
+      auth_headers[0] = (settings.auth_header_name.as_str(), api_key.to_owned());
+    }
+    auth_headers
+  };
+  let auth_headers: Vec<(&str, &str)> = auth_headers
+    .iter()
+    .map(|(name, value)| (*name, value.as_str()))
+    .collect();
+  let form = settings.request_content_type == RequestContentType::Form;
+  let body = if form {
+    form_encode(&body)
+  }
+  else {
+    body
+  };
+  let mut request = client::build_request(
+    method,
+    uri,
+    &auth_headers,
+    &settings.user_agent,
+    settings.compress_request,
+    body,
+  );
+  if !settings.host_override.is_empty() {
+    let host = HeaderValue::from_str(&settings.host_override).unwrap();
+    request.headers_mut().insert(HOST, host);
+  }
+  if form {
+    let content_type = HeaderValue::from_static("application/x-www-form-urlencoded");
+    request.headers_mut().insert(CONTENT_TYPE, content_type);
+  }
+  request
+}
+
+/// Re-encodes a JSON object as a form, with a field for each of its fields. Strings are sent as
+/// they are, other values as JSON, and null fields are left out.
+pub(super) fn form_encode(json: &[u8]) -> Vec<u8> {
+  let fields = match serde_json::from_slice::<serde_json::Value>(json) {
+    Ok(serde_json::Value::Object(fields)) => fields,
+    _ => return json.to_vec(),
+  };
+  let mut form = String::new();
+  for (name, value) in fields {
+    let value = match value {
+      serde_json::Value::Null => continue,
+      serde_json::Value::String(value) => value,
+      value => value.to_string(),
+    };
+    if !form.is_empty() {
+      form.push('&');
+    }
+    form.push_str(&percent_encode(&name));
+    form.push('=');
+    form.push_str(&percent_encode(&value));
+  }
+  form.into_bytes()
+}
+
+/// Percent-encodes `text` for a form, with spaces as `+`.
+pub(super) fn percent_encode(text: &str) -> String {
+  let mut encoded = String::with_capacity(text.len());
+  for byte in text.bytes() {
+    match byte {
+      b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+        encoded.push(byte as char);
+      },
+      b' ' => encoded.push('+'),
+      byte => encoded.push_str(&format!("%{:02X}", byte)),
+    }
+  }
+  encoded
+}
+
+pub(super) fn build_client(settings: &Settings) -> HttpsClient {
+  let pool_idle_timeout = (settings.pool_idle_timeout > 0)
+    .then(|| Duration::from_secs(settings.pool_idle_timeout.into()));
+  let connect_timeout = (settings.connect_timeout > 0)
+    .then(|| Duration::from_secs(settings.connect_timeout.into()));
+  client::build_client(
+    connect_timeout,
+    pool_idle_timeout,
+    settings.pool_max_idle_per_host as usize,
+    (!settings.tls_sni.is_empty()).then(|| settings.tls_sni.clone()),
+    settings.allow_http || settings.provider == Provider::Ollama,
+  )
+}
+
+/// Sends `request`, giving up once the configured timeout has elapsed.
+pub(super) async fn send_request(
+  client: &dyn ChatTransport,
+  settings: &Settings,
+  request: Request<Body>,
+) -> Result<Response<Body>, String> {
+  let timeout = (settings.timeout > 0).then(|| Duration::from_secs(settings.timeout.into()));
+  client::send_request(client, timeout, request).await
+}
+
+/// Checks `inputs` against the moderation endpoint, returning whether each one was flagged.
+pub(super) async fn moderate(
+  client: &dyn ChatTransport,
+  settings: &Settings,
+  api_key: &str,
+  inputs: Vec<String>,
+) -> Result<Vec<bool>, String> {
+  let request_body = serde_json::to_vec(&OpenaiModerationRequest { input: inputs }).unwrap();
+  let uri = moderation_endpoint(settings)
+    .parse()
+    .map_err(|e| format!("invalid moderation endpoint: {}", e))?;
+  let request = build_request(Method::POST, uri, settings, api_key, request_body);
+  let response = send_request(client, settings, request).await?;
+  if !response.status().is_success() {
+    return Err(format!("HTTP error from moderation API: {}", response.status()));
+  }
+  let max_len = settings.max_response_bytes as usize;
+  let response_body = read_body(response.into_body(), max_len, |_| {})
+    .await
+    .map_err(|e| match e {
+      ReadError::TooLarge => format!(
+        "response from moderation API exceeded max-response-bytes ({})",
+        settings.max_response_bytes
+      ),
+      ReadError::Http(e) => e.to_string(),
+    })?;
+  let response_body: OpenaiModerationResponse =
+    serde_json::from_slice(&response_body).map_err(|e| e.to_string())?;
+  Ok(response_body.results.iter().map(|result| result.flagged).collect())
+}
+
+pub(super) fn parse_response(
+  settings: &Settings,
+  framing: Option<sse::Framing>,
+  body: &[u8],
+) -> Result<OpenAiChatCompletionResponse, serde_json::Error> {
+  if let Some(framing) = framing {
+    gstreamer::debug!(CAT, "reassembling {:?} streamed response", framing);
+  }
+  client::parse_body(settings.provider.stream_format(), framing, body)
+}
+
+pub(super) fn truncated_response(body: &[u8]) -> String {
+  let mut body = String::from_utf8_lossy(body).into_owned();
+  if body.len() > LAST_RESPONSE_MAX_LEN {
+    let mut len = LAST_RESPONSE_MAX_LEN;
+    while !body.is_char_boundary(len) {
+      len -= 1;
+    }
+    body.truncate(len);
+  }
+  body
+}
+
+/// Returns how long to wait before sending a request so that it starts at least
+/// min-request-interval after the previous one, and records when it will be sent.
+pub(super) fn pacing_delay(settings: &Settings, state: &mut State) -> Duration {
+  let now = Instant::now();
+  let interval = Duration::from_millis(settings.min_request_interval.into());
+  let send_at = match state.last_request_sent {
+    Some(last) if !interval.is_zero() => now.max(last + interval),
+    _ => now,
+  };
+  state.last_request_sent = Some(send_at);
+  send_at - now
+}
+
+/// Returns whether nothing pushed on the src pad would reach downstream: the pad is flushing, or
+/// unlinked when require-src is set.
+pub(super) fn output_unused(pad: &Pad, require_src: bool) -> bool {
+  pad.pad_flags().contains(gstreamer::PadFlags::FLUSHING) || (require_src && !pad.is_linked())
+}
+
+/// Cancels `cancellation`, and with it the request in flight, as soon as its output becomes
+/// unused, until `watch` is cancelled once the request is done.
+pub(super) async fn abort_when_output_unused(
+  pad: Pad,
+  require_src: bool,
+  cancellation: CancellationToken,
+  watch: CancellationToken,
+) {
+  let mut interval = tokio::time::interval(OUTPUT_CHECK_INTERVAL);
+  while watch.run_until_cancelled(interval.tick()).await.is_some() {
+    if output_unused(&pad, require_src) {
+      gstreamer::debug!(CAT, "src pad unusable, aborting the request in flight");
+      cancellation.cancel();
+      break;
+    }
+  }
+}
+
+/// Returns the span wrapping a request when tracing-spans is set. The API key is not recorded.
+#[cfg(feature = "tracing")]
+pub(super) fn request_span(settings: &Settings, conversation_id: &str) -> tracing::Span {
+  if !settings.tracing_spans {
+    return tracing::Span::none();
+  }
+  tracing::info_span!(
+    "openaichat_request",
+    model = %settings.model,
+    conversation_id,
+    latency_ms = tracing::field::Empty,
+    status = tracing::field::Empty,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn percent_encode_escapes_reserved_bytes() {
+    assert_eq!(percent_encode("a-Z_0.9~"), "a-Z_0.9~");
+    assert_eq!(percent_encode("a b&c=d"), "a+b%26c%3Dd");
+    assert_eq!(percent_encode("é"), "%C3%A9");
+  }
+
+  #[test]
+  fn form_encode_encodes_each_field() {
+    let json = br#"{"model":"gpt 4","n":2,"stop":["a"],"user":null}"#;
+    assert_eq!(
+      String::from_utf8(form_encode(json)).unwrap(),
+      "model=gpt+4&n=2&stop=%5B%22a%22%5D"
+    );
+  }
+
+  #[test]
+  fn form_encode_passes_other_json_through() {
+    assert_eq!(form_encode(b"[1,2]"), b"[1,2]");
+    assert_eq!(form_encode(b"not json"), b"not json");
+  }
+
+  #[test]
+  fn jittered_temperature_stays_in_range() {
+    assert_eq!(jittered_temperature(0.7, 0.0), 0.7);
+    for _ in 0..100 {
+      assert!((0.5..=1.5).contains(&jittered_temperature(1.0, 0.5)));
+      assert!((1.5..=2.0).contains(&jittered_temperature(1.9, 0.4)));
+    }
+  }
+}
@@ -0,0 +1,145 @@
+//! Buffer meta overriding request parameters for a single input buffer.
+
+use std::{fmt, mem, ptr};
+
+use gstreamer::{
+  ffi,
+  glib::{
+    self,
+    translate::{from_glib, IntoGlib},
+  },
+  meta::{MetaAPI, MetaAPIExt},
+  BufferRef, MetaRefMut,
+};
+use once_cell::sync::Lazy;
+
+/// Request parameters that take precedence over the element's properties for the request sent for
+/// one input buffer. `None` keeps the element's value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpenAiRequestParams {
+  pub temperature: Option<f64>,
+  pub seed: Option<i64>,
+  pub max_tokens: Option<u32>,
+  /// Priority of the request, in place of default-priority.
+  pub priority: Option<i32>,
+}
+
+/// Meta carrying [`OpenAiRequestParams`] on an input buffer, for pipelines that compute request
+/// parameters per buffer.
+#[repr(transparent)]
+pub struct OpenAiRequestParamsMeta(imp::OpenAiRequestParamsMeta);
+
+// The parameters are plain data, and are never changed once attached.
+unsafe impl Send for OpenAiRequestParamsMeta {}
+unsafe impl Sync for OpenAiRequestParamsMeta {}
+
+impl OpenAiRequestParamsMeta {
+  pub fn add(
+    buffer: &mut BufferRef,
+    params: OpenAiRequestParams,
+  ) -> MetaRefMut<'_, Self, gstreamer::meta::Standalone> {
+    unsafe {
+      // Moved into the meta by its init function.
+      let mut params = mem::ManuallyDrop::new(params);
+      let meta = ffi::gst_buffer_add_meta(
+        buffer.as_mut_ptr(),
+        imp::meta_info(),
+        &mut *params as *mut OpenAiRequestParams as glib::ffi::gpointer,
+      ) as *mut imp::OpenAiRequestParamsMeta;
+      Self::from_mut_ptr(buffer, meta)
+    }
+  }
+
+  pub fn params(&self) -> &OpenAiRequestParams {
+    &self.0.params
+  }
+}
+
+unsafe impl MetaAPI for OpenAiRequestParamsMeta {
+  type GstType = imp::OpenAiRequestParamsMeta;
+
+  fn meta_api() -> glib::Type {
+    imp::meta_api()
+  }
+}
+
+impl fmt::Debug for OpenAiRequestParamsMeta {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("OpenAiRequestParamsMeta")
+      .field(self.params())
+      .finish()
+  }
+}
+
+mod imp {
+  use super::*;
+
+  #[repr(C)]
+  pub struct OpenAiRequestParamsMeta {
+    parent: ffi::GstMeta,
+    pub(super) params: OpenAiRequestParams,
+  }
+
+  pub(super) fn meta_api() -> glib::Type {
+    static TYPE: Lazy<glib::Type> = Lazy::new(|| unsafe {
+      // No tags, so that the meta is kept by transformations of the buffer.
+      let mut tags = [ptr::null::<std::os::raw::c_char>()];
+      let meta_api: glib::Type = from_glib(ffi::gst_meta_api_type_register(
+        glib::gstr!("OpenAiRequestParamsMetaAPI").as_ptr(),
+        tags.as_mut_ptr(),
+      ));
+      assert_ne!(meta_api, glib::Type::INVALID);
+      meta_api
+    });
+    *TYPE
+  }
+
+  unsafe extern "C" fn init(
+    meta: *mut ffi::GstMeta,
+    params: glib::ffi::gpointer,
+    _buffer: *mut ffi::GstBuffer,
+  ) -> glib::ffi::gboolean {
+    let meta = &mut *(meta as *mut OpenAiRequestParamsMeta);
+    ptr::write(&mut meta.params, ptr::read(params as *const OpenAiRequestParams));
+    true.into_glib()
+  }
+
+  unsafe extern "C" fn free(meta: *mut ffi::GstMeta, _buffer: *mut ffi::GstBuffer) {
+    let meta = &mut *(meta as *mut OpenAiRequestParamsMeta);
+    ptr::drop_in_place(&mut meta.params);
+  }
+
+  unsafe extern "C" fn transform(
+    dest: *mut ffi::GstBuffer,
+    meta: *mut ffi::GstMeta,
+    _buffer: *mut ffi::GstBuffer,
+    _type: glib::ffi::GQuark,
+    _data: glib::ffi::gpointer,
+  ) -> glib::ffi::gboolean {
+    let meta = &*(meta as *mut OpenAiRequestParamsMeta);
+    super::OpenAiRequestParamsMeta::add(BufferRef::from_mut_ptr(dest), meta.params.clone());
+    true.into_glib()
+  }
+
+  pub(super) fn meta_info() -> *const ffi::GstMetaInfo {
+    struct MetaInfo(ptr::NonNull<ffi::GstMetaInfo>);
+    // The info is registered once and never changed.
+    unsafe impl Send for MetaInfo {}
+    unsafe impl Sync for MetaInfo {}
+
+    static META_INFO: Lazy<MetaInfo> = Lazy::new(|| unsafe {
+      MetaInfo(
+        ptr::NonNull::new(ffi::gst_meta_register(
+          meta_api().into_glib(),
+          glib::gstr!("OpenAiRequestParamsMeta").as_ptr(),
+          mem::size_of::<OpenAiRequestParamsMeta>(),
+          Some(init),
+          Some(free),
+          Some(transform),
+        ) as *mut ffi::GstMetaInfo)
+        .expect("failed to register OpenAiRequestParamsMeta"),
+      )
+    });
+    META_INFO.0.as_ptr()
+  }
+}
@@ -1,35 +1,370 @@
-use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-#[derive(Serialize)]
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Serialize, Default)]
 pub struct OpenaiChatCompletionRequest {
   pub model: String,
   pub messages: Vec<OpenaiChatCompletionMessage>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub temperature: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub n: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub seed: Option<i64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_tokens: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stream: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<HashMap<String, String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub prompt_cache_key: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub modalities: Option<Vec<String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub audio: Option<OpenaiChatCompletionAudioConfig>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub logprobs: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub top_logprobs: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stream_options: Option<OpenaiStreamOptions>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize)]
+pub struct OpenaiChatCompletionAudioConfig {
+  pub voice: String,
+  pub format: String,
+}
+
+#[derive(Serialize)]
+pub struct OpenaiStreamOptions {
+  pub include_usage: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct OpenaiChatCompletionMessage {
   pub role: String,
+  /// Null in audio responses, whose text is in the audio transcript.
+  #[serde(default, deserialize_with = "null_as_default")]
   pub content: String,
+  /// Distinguishes participants sharing a role, e.g. agents of a multi-agent conversation.
+  #[serde(default, skip_serializing_if = "String::is_empty")]
+  pub name: String,
+  #[serde(default, skip_serializing)]
+  pub audio: Option<OpenaiChatCompletionAudio>,
+  /// Summary of the reasoning of reasoning models, in `reasoning` with some providers. Not sent
+  /// back in later requests.
+  #[serde(default, alias = "reasoning", deserialize_with = "null_as_default", skip_serializing)]
+  pub reasoning_content: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct OpenaiChatCompletionAudio {
+  /// Base64-encoded audio in the requested format.
+  pub data: String,
+  pub transcript: String,
+}
+
+fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+  D: Deserializer<'de>,
+  T: Default + Deserialize<'de>,
+{
+  Ok(Option::deserialize(deserializer)?.unwrap_or_default())
+}
+
+#[derive(Deserialize, Default, Debug)]
 pub struct OpenAiChatCompletionResponse {
   pub id: String,
   pub object: String,
   pub created: u64,
+  pub model: Option<String>,
+  pub system_fingerprint: Option<String>,
   pub choices: Vec<OpenaiChatCompletionResponseChoice>,
+  pub usage: Option<OpenAiChatCompletionResponseUsage>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default, Debug)]
 pub struct OpenaiChatCompletionResponseChoice {
   pub index: usize,
   pub message: OpenaiChatCompletionMessage,
   pub finish_reason: String,
+  /// Present when logprobs were requested.
+  #[serde(default)]
+  pub logprobs: Option<OpenaiChatCompletionLogprobs>,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct OpenaiChatCompletionLogprobs {
+  #[serde(default, deserialize_with = "null_as_default")]
+  pub content: Vec<OpenaiTokenLogprob>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OpenaiTokenLogprob {
+  pub token: String,
+  pub logprob: f64,
+  /// The most likely tokens at this position, when top_logprobs was requested.
+  #[serde(default)]
+  pub top_logprobs: Vec<OpenaiTopLogprob>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OpenaiTopLogprob {
+  pub token: String,
+  pub logprob: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct OpenAiChatCompletionResponseUsage {
   pub prompt_tokens: u64,
   pub completion_tokens: u64,
   pub total_tokens: u64,
 }
+
+#[derive(Serialize)]
+pub struct OpenaiModerationRequest {
+  pub input: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiModerationResponse {
+  pub results: Vec<OpenaiModerationResult>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiModerationResult {
+  pub flagged: bool,
+}
+
+#[derive(Deserialize)]
+pub struct OpenAiChatCompletionChunk {
+  pub id: String,
+  pub created: u64,
+  pub model: Option<String>,
+  pub system_fingerprint: Option<String>,
+  pub choices: Vec<OpenaiChatCompletionChunkChoice>,
+  pub usage: Option<OpenAiChatCompletionResponseUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiChatCompletionChunkChoice {
+  pub index: usize,
+  pub delta: OpenaiChatCompletionDelta,
+  pub finish_reason: Option<String>,
+  #[serde(default)]
+  pub logprobs: Option<OpenaiChatCompletionLogprobs>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiChatCompletionDelta {
+  pub role: Option<String>,
+  pub content: Option<String>,
+  #[serde(default, alias = "reasoning")]
+  pub reasoning_content: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct OpenaiResponsesRequest {
+  pub model: String,
+  pub input: Vec<OpenaiChatCompletionMessage>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub previous_response_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub max_output_tokens: Option<u32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub temperature: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub metadata: Option<HashMap<String, String>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub prompt_cache_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiResponsesResponse {
+  pub id: String,
+  pub created_at: u64,
+  pub model: Option<String>,
+  pub status: Option<String>,
+  pub output: Vec<OpenaiResponsesOutput>,
+  pub usage: Option<OpenaiResponsesUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiResponsesOutput {
+  #[serde(rename = "type")]
+  pub kind: String,
+  #[serde(default)]
+  pub content: Vec<OpenaiResponsesContent>,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiResponsesContent {
+  #[serde(rename = "type")]
+  pub kind: String,
+  #[serde(default)]
+  pub text: String,
+}
+
+#[derive(Deserialize)]
+pub struct OpenaiResponsesUsage {
+  pub input_tokens: u64,
+  pub output_tokens: u64,
+  pub total_tokens: u64,
+}
+
+impl From<OpenaiResponsesResponse> for OpenAiChatCompletionResponse {
+  fn from(response: OpenaiResponsesResponse) -> Self {
+    let content = response
+      .output
+      .iter()
+      .filter(|output| output.kind == "message")
+      .flat_map(|output| &output.content)
+      .filter(|content| content.kind == "output_text")
+      .map(|content| content.text.as_str())
+      .collect();
+    let finish_reason = match response.status.as_deref() {
+      Some("incomplete") => "length",
+      _ => "stop",
+    };
+    OpenAiChatCompletionResponse {
+      id: response.id,
+      object: "response".into(),
+      created: response.created_at,
+      model: response.model,
+      choices: vec![OpenaiChatCompletionResponseChoice {
+        message: OpenaiChatCompletionMessage {
+          role: "assistant".into(),
+          content,
+          ..Default::default()
+        },
+        finish_reason: finish_reason.into(),
+        ..Default::default()
+      }],
+      usage: response.usage.map(|usage| OpenAiChatCompletionResponseUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: usage.total_tokens,
+      }),
+      ..Default::default()
+    }
+  }
+}
+
+/// Request of the Anthropic Messages API, which takes the system prompt apart from the messages
+/// and requires max_tokens.
+#[derive(Serialize, Default)]
+pub struct AnthropicMessagesRequest {
+  pub model: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub system: Option<String>,
+  pub messages: Vec<OpenaiChatCompletionMessage>,
+  pub max_tokens: u32,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub temperature: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub stream: Option<bool>,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicMessagesResponse {
+  pub id: String,
+  pub model: Option<String>,
+  pub content: Vec<AnthropicContentBlock>,
+  pub stop_reason: Option<String>,
+  pub usage: Option<AnthropicStreamUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicContentBlock {
+  #[serde(rename = "type")]
+  pub kind: String,
+  #[serde(default)]
+  pub text: String,
+}
+
+impl From<AnthropicMessagesResponse> for OpenAiChatCompletionResponse {
+  fn from(response: AnthropicMessagesResponse) -> Self {
+    let content = response
+      .content
+      .iter()
+      .filter(|block| block.kind == "text")
+      .map(|block| block.text.as_str())
+      .collect();
+    OpenAiChatCompletionResponse {
+      id: response.id,
+      object: "chat.completion".into(),
+      model: response.model,
+      choices: vec![OpenaiChatCompletionResponseChoice {
+        message: OpenaiChatCompletionMessage {
+          role: "assistant".into(),
+          content,
+          ..Default::default()
+        },
+        finish_reason: response.stop_reason.map(anthropic_finish_reason).unwrap_or_default(),
+        ..Default::default()
+      }],
+      usage: response.usage.map(|usage| OpenAiChatCompletionResponseUsage {
+        prompt_tokens: usage.input_tokens,
+        completion_tokens: usage.output_tokens,
+        total_tokens: usage.input_tokens + usage.output_tokens,
+      }),
+      ..Default::default()
+    }
+  }
+}
+
+/// Translates an Anthropic stop_reason into the matching OpenAI finish_reason.
+pub fn anthropic_finish_reason(stop_reason: String) -> String {
+  match stop_reason.as_str() {
+    "end_turn" | "stop_sequence" => "stop".into(),
+    "max_tokens" => "length".into(),
+    _ => stop_reason,
+  }
+}
+
+/// Event of a streamed Anthropic message, in the shape of its server-sent events.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnthropicStreamEvent {
+  MessageStart {
+    message: AnthropicStreamMessage,
+  },
+  ContentBlockDelta {
+    delta: AnthropicContentDelta,
+  },
+  MessageDelta {
+    delta: AnthropicMessageDelta,
+    usage: Option<AnthropicStreamUsage>,
+  },
+  #[serde(other)]
+  Other,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicStreamMessage {
+  pub id: String,
+  pub model: Option<String>,
+  pub usage: Option<AnthropicStreamUsage>,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicContentDelta {
+  /// Only set for text deltas.
+  pub text: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicMessageDelta {
+  pub stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AnthropicStreamUsage {
+  #[serde(default)]
+  pub input_tokens: u64,
+  #[serde(default)]
+  pub output_tokens: u64,
+}
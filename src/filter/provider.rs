@@ -0,0 +1,99 @@
+//! API providers the element and the client can talk to, and how their requests differ.
+
+use gstreamer::glib;
+
+use crate::filter::{
+  openai_model::{AnthropicMessagesRequest, OpenaiChatCompletionMessage},
+  sse::StreamFormat,
+};
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// The Messages API requires max_tokens, which is only set per request otherwise.
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u32 = 1024;
+
+/// The API behind the endpoint, which sets how requests are authenticated and shaped.
+#[derive(Debug, Default, Eq, PartialEq, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstOpenaiChatProvider")]
+pub enum Provider {
+  #[default]
+  #[enum_value(name = "OpenAI", nick = "openai")]
+  OpenAi,
+  #[enum_value(name = "Azure OpenAI", nick = "azure")]
+  Azure,
+  #[enum_value(name = "Anthropic", nick = "anthropic")]
+  Anthropic,
+  #[enum_value(name = "Ollama", nick = "ollama")]
+  Ollama,
+}
+
+impl Provider {
+  /// URL of the chat endpoint when neither endpoint nor base-url is set. OpenAI and Azure use
+  /// OPENAI_ENDPOINT instead.
+  pub(crate) fn default_endpoint(self) -> Option<&'static str> {
+    match self {
+      Provider::Anthropic => Some("https://api.anthropic.com/v1/messages"),
+      Provider::Ollama => Some("http://localhost:11434/v1/chat/completions"),
+      Provider::OpenAi | Provider::Azure => None,
+    }
+  }
+
+  /// Returns the headers authenticating a request with `api_key`, the one carrying the key first.
+  pub(crate) fn auth_headers(self, api_key: &str) -> Vec<(&'static str, String)> {
+    match self {
+      Provider::OpenAi | Provider::Ollama => vec![("Authorization", format!("Bearer {}", api_key))],
+      Provider::Azure => vec![("api-key", api_key.to_owned())],
+      Provider::Anthropic => vec![
+        ("x-api-key", api_key.to_owned()),
+        ("anthropic-version", ANTHROPIC_VERSION.to_owned()),
+      ],
+    }
+  }
+
+  pub(crate) fn stream_format(self) -> StreamFormat {
+    match self {
+      Provider::Anthropic => StreamFormat::Anthropic,
+      _ => StreamFormat::OpenAi,
+    }
+  }
+
+  pub(crate) fn messages(
+    self,
+    messages: Vec<OpenaiChatCompletionMessage>,
+  ) -> Vec<OpenaiChatCompletionMessage> {
+    messages
+      .into_iter()
+      .map(|message| OpenaiChatCompletionMessage {
+        // Anthropic rejects unknown message fields.
+        name: match self {
+          Provider::Anthropic => String::new(),
+          _ => message.name,
+        },
+        ..message
+      })
+      .collect()
+  }
+}
+
+/// Builds a Messages API request from the history, whose system messages go in the top-level
+/// system field. Only the fields Anthropic supports are sent.
+pub(crate) fn anthropic_request(
+  model: &str,
+  messages: Vec<OpenaiChatCompletionMessage>,
+  max_tokens: Option<u32>,
+  temperature: Option<f64>,
+  stream: bool,
+) -> AnthropicMessagesRequest {
+  let (system, messages): (Vec<_>, Vec<_>) =
+    messages.into_iter().partition(|message| message.role == "system");
+  let system: Vec<_> = system.into_iter().map(|message| message.content).collect();
+  AnthropicMessagesRequest {
+    model: model.to_owned(),
+    system: (!system.is_empty()).then(|| system.join("\n\n")),
+    messages: Provider::Anthropic.messages(messages),
+    max_tokens: max_tokens.unwrap_or(DEFAULT_ANTHROPIC_MAX_TOKENS),
+    temperature,
+    stream: stream.then_some(true),
+  }
+}
@@ -0,0 +1,87 @@
+//! Safe wrapper around GLib's `GRegex`, which the bindings do not expose. GLib is always there
+//! with GStreamer, so this avoids a regex dependency.
+
+use std::{fmt, ptr::NonNull};
+
+use gstreamer::glib::{
+  self, ffi,
+  translate::{from_glib_full, ToGlibPtr},
+};
+
+/// A compiled Perl-compatible regular expression.
+pub struct Regex {
+  regex: NonNull<ffi::GRegex>,
+  pattern: String,
+}
+
+// A GRegex is immutable once compiled, and GLib documents it as usable from several threads.
+unsafe impl Send for Regex {}
+unsafe impl Sync for Regex {}
+
+impl Regex {
+  pub fn new(pattern: &str) -> Result<Self, glib::Error> {
+    unsafe {
+      let mut error = std::ptr::null_mut();
+      let regex = ffi::g_regex_new(pattern.to_glib_none().0, 0, 0, &mut error);
+      match NonNull::new(regex) {
+        Some(regex) => Ok(Self {
+          regex,
+          pattern: pattern.to_owned(),
+        }),
+        None => Err(from_glib_full(error)),
+      }
+    }
+  }
+
+  pub fn as_str(&self) -> &str {
+    &self.pattern
+  }
+
+  /// Replaces every match in `text` with `replacement`, in which `\0` is the whole match, `\1` to
+  /// `\9` are groups and `\g<name>` is a named group.
+  pub fn replace_all(&self, text: &str, replacement: &str) -> Result<String, glib::Error> {
+    unsafe {
+      let mut error = std::ptr::null_mut();
+      let replaced = ffi::g_regex_replace(
+        self.regex.as_ptr(),
+        text.to_glib_none().0,
+        text.len() as _,
+        0,
+        replacement.to_glib_none().0,
+        0,
+        &mut error,
+      );
+      if replaced.is_null() {
+        Err(from_glib_full(error))
+      }
+      else {
+        let replaced: glib::GString = from_glib_full(replaced);
+        Ok(replaced.into())
+      }
+    }
+  }
+}
+
+impl Clone for Regex {
+  fn clone(&self) -> Self {
+    unsafe {
+      ffi::g_regex_ref(self.regex.as_ptr());
+    }
+    Self {
+      regex: self.regex,
+      pattern: self.pattern.clone(),
+    }
+  }
+}
+
+impl Drop for Regex {
+  fn drop(&mut self) {
+    unsafe { ffi::g_regex_unref(self.regex.as_ptr()) }
+  }
+}
+
+impl fmt::Debug for Regex {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Regex").field(&self.pattern).finish()
+  }
+}
@@ -0,0 +1,353 @@
+use crate::filter::openai_model::{
+  anthropic_finish_reason, AnthropicStreamEvent, OpenAiChatCompletionChunk, OpenAiChatCompletionResponse,
+  OpenAiChatCompletionResponseUsage, OpenaiChatCompletionMessage,
+  OpenaiChatCompletionResponseChoice,
+};
+
+/// Shape of the events of a streamed response, which depends on the provider.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StreamFormat {
+  /// Chat completion chunks, with content in `choices[].delta.content`.
+  #[default]
+  OpenAi,
+  /// Message events, with content in the `delta.text` of `content_block_delta` events.
+  Anthropic,
+}
+
+/// How the chunks of a streamed response are delimited.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+  /// Server-sent events, with a chunk in each `data:` field.
+  #[default]
+  EventStream,
+  /// Newline-delimited JSON, with a chunk on each line, as streamed by some local inference
+  /// servers.
+  Ndjson,
+}
+
+impl Framing {
+  /// Returns the framing of responses with the given `Content-Type`, if they are streamed.
+  pub fn from_content_type(content_type: &str) -> Option<Self> {
+    if content_type.starts_with("text/event-stream") {
+      Some(Framing::EventStream)
+    }
+    else if content_type.starts_with("application/x-ndjson") {
+      Some(Framing::Ndjson)
+    }
+    else {
+      None
+    }
+  }
+}
+
+/// Returns the chunks in `body`, up to the `[DONE]` sentinel.
+pub fn chunks(framing: Framing, body: &str) -> impl Iterator<Item = &str> {
+  body
+    .lines()
+    .filter_map(move |line| match framing {
+      Framing::EventStream => line.strip_prefix("data:"),
+      Framing::Ndjson => Some(line),
+    })
+    .map(str::trim)
+    .filter(|data| !data.is_empty())
+    .take_while(|data| *data != "[DONE]")
+}
+
+/// Reassembles a streamed response into the chat completion the API returns without streaming.
+pub fn reassemble(
+  format: StreamFormat,
+  framing: Framing,
+  body: &str,
+) -> Result<OpenAiChatCompletionResponse, serde_json::Error> {
+  let chunks = chunks(framing, body);
+  match format {
+    StreamFormat::OpenAi => reassemble_chat_completion(chunks),
+    StreamFormat::Anthropic => reassemble_anthropic_message(chunks),
+  }
+}
+
+/// Highest number of choices a request can ask for. Chunks of choices beyond it are ignored, so
+/// that the index sent by the server cannot grow the response without limit.
+const MAX_CHOICES: usize = 128;
+
+fn reassemble_chat_completion<'a>(
+  chunks: impl Iterator<Item = &'a str>,
+) -> Result<OpenAiChatCompletionResponse, serde_json::Error> {
+  let mut response = OpenAiChatCompletionResponse {
+    object: "chat.completion".into(),
+    ..Default::default()
+  };
+  for data in chunks {
+    let chunk: OpenAiChatCompletionChunk = serde_json::from_str(data)?;
+    response.id = chunk.id;
+    response.created = chunk.created;
+    response.model = chunk.model.or(response.model);
+    response.system_fingerprint = chunk.system_fingerprint.or(response.system_fingerprint);
+    response.usage = chunk.usage.or(response.usage);
+    for chunk_choice in chunk.choices {
+      if chunk_choice.index >= MAX_CHOICES {
+        continue;
+      }
+      while response.choices.len() <= chunk_choice.index {
+        response.choices.push(OpenaiChatCompletionResponseChoice {
+          index: response.choices.len(),
+          message: OpenaiChatCompletionMessage {
+            role: "assistant".into(),
+            ..Default::default()
+          },
+          ..Default::default()
+        });
+      }
+      let choice = &mut response.choices[chunk_choice.index];
+      if let Some(role) = chunk_choice.delta.role {
+        choice.message.role = role;
+      }
+      if let Some(content) = chunk_choice.delta.content {
+        choice.message.content.push_str(&content);
+      }
+      if let Some(reasoning) = chunk_choice.delta.reasoning_content {
+        choice.message.reasoning_content.push_str(&reasoning);
+      }
+      if let Some(finish_reason) = chunk_choice.finish_reason {
+        choice.finish_reason = finish_reason;
+      }
+      if let Some(logprobs) = chunk_choice.logprobs {
+        choice
+          .logprobs
+          .get_or_insert_with(Default::default)
+          .content
+          .extend(logprobs.content);
+      }
+    }
+  }
+  Ok(response)
+}
+
+fn reassemble_anthropic_message<'a>(
+  chunks: impl Iterator<Item = &'a str>,
+) -> Result<OpenAiChatCompletionResponse, serde_json::Error> {
+  let mut response = OpenAiChatCompletionResponse {
+    object: "chat.completion".into(),
+    choices: vec![OpenaiChatCompletionResponseChoice {
+      message: OpenaiChatCompletionMessage {
+        role: "assistant".into(),
+        ..Default::default()
+      },
+      ..Default::default()
+    }],
+    ..Default::default()
+  };
+  let mut prompt_tokens = 0;
+  let mut completion_tokens = 0;
+  for data in chunks {
+    match serde_json::from_str(data)? {
+      AnthropicStreamEvent::MessageStart { message } => {
+        response.id = message.id;
+        response.model = message.model;
+        if let Some(usage) = message.usage {
+          prompt_tokens = usage.input_tokens;
+          completion_tokens = usage.output_tokens;
+        }
+      },
+      AnthropicStreamEvent::ContentBlockDelta { delta } => {
+        if let Some(text) = delta.text {
+          response.choices[0].message.content.push_str(&text);
+        }
+      },
+      AnthropicStreamEvent::MessageDelta { delta, usage } => {
+        if let Some(stop_reason) = delta.stop_reason {
+          response.choices[0].finish_reason = anthropic_finish_reason(stop_reason);
+        }
+        if let Some(usage) = usage {
+          completion_tokens = usage.output_tokens;
+        }
+      },
+      AnthropicStreamEvent::Other => {},
+    }
+  }
+  response.usage = Some(OpenAiChatCompletionResponseUsage {
+    prompt_tokens,
+    completion_tokens,
+    total_tokens: prompt_tokens + completion_tokens,
+  });
+  Ok(response)
+}
+
+/// Calls `on_delta` with the parts of the first choice's content in the chunk `data`.
+fn content_deltas(format: StreamFormat, data: &str, mut on_delta: impl FnMut(&str)) {
+  match format {
+    StreamFormat::OpenAi => {
+      if let Ok(chunk) = serde_json::from_str::<OpenAiChatCompletionChunk>(data) {
+        let content = chunk
+          .choices
+          .iter()
+          .filter(|choice| choice.index == 0)
+          .filter_map(|choice| choice.delta.content.as_deref());
+        for content in content {
+          on_delta(content);
+        }
+      }
+    },
+    StreamFormat::Anthropic => {
+      if let Ok(AnthropicStreamEvent::ContentBlockDelta { delta }) = serde_json::from_str(data) {
+        if let Some(text) = &delta.text {
+          on_delta(text);
+        }
+      }
+    },
+  }
+}
+
+/// Incrementally extracts the first choice's content from a streamed chat completion as its body
+/// is received.
+#[derive(Default)]
+pub struct DeltaParser {
+  format: StreamFormat,
+  framing: Framing,
+  line_start: usize,
+}
+
+impl DeltaParser {
+  pub fn new(format: StreamFormat, framing: Framing) -> Self {
+    Self {
+      format,
+      framing,
+      line_start: 0,
+    }
+  }
+
+  /// Parses the complete lines of `body` which have not been parsed yet, calling `on_delta` with
+  /// each part of the content. `body` is everything received so far.
+  pub fn parse(&mut self, body: &[u8], mut on_delta: impl FnMut(&str)) {
+    while let Some(line_len) = body[self.line_start..].iter().position(|b| *b == b'\n') {
+      let line = String::from_utf8_lossy(&body[self.line_start..self.line_start + line_len]);
+      for data in chunks(self.framing, &line) {
+        content_deltas(self.format, data, &mut on_delta);
+      }
+      self.line_start += line_len + 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const CHAT_COMPLETION_STREAM: &str = concat!(
+    r#"data: {"id":"1","object":"chat.completion.chunk","created":7,"model":"gpt-4o","choices":"#,
+    r#"[{"index":0,"delta":{"role":"assistant","content":"Hel"}}]}"#,
+    "\n\n",
+    r#"data: {"id":"1","object":"chat.completion.chunk","created":7,"choices":"#,
+    r#"[{"index":1,"delta":{"content":"Other"}},{"index":0,"delta":{"content":"lo"}}]}"#,
+    "\n\n",
+    r#"data: {"id":"1","object":"chat.completion.chunk","created":7,"choices":"#,
+    r#"[{"index":0,"delta":{},"finish_reason":"stop"}],"#,
+    r#""usage":{"prompt_tokens":3,"completion_tokens":2,"total_tokens":5}}"#,
+    "\n\n",
+    "data: [DONE]\n\n",
+    "data: not json\n\n",
+  );
+
+  const ANTHROPIC_STREAM: &str = concat!(
+    "event: message_start\n",
+    r#"data: {"type":"message_start","message":{"id":"msg_1","model":"claude","#,
+    r#""usage":{"input_tokens":4,"output_tokens":1}}}"#,
+    "\n\n",
+    "event: content_block_delta\n",
+    r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+    "\n\n",
+    "event: ping\n",
+    r#"data: {"type":"ping"}"#,
+    "\n\n",
+    "event: message_delta\n",
+    r#"data: {"type":"message_delta","delta":{"stop_reason":"end_turn"},"#,
+    r#""usage":{"output_tokens":6}}"#,
+    "\n\n",
+  );
+
+  #[test]
+  fn framing_from_content_type() {
+    assert_eq!(
+      Framing::from_content_type("text/event-stream; charset=utf-8"),
+      Some(Framing::EventStream)
+    );
+    assert_eq!(Framing::from_content_type("application/x-ndjson"), Some(Framing::Ndjson));
+    assert_eq!(Framing::from_content_type("application/json"), None);
+  }
+
+  #[test]
+  fn reassemble_chat_completion_stream() {
+    let response =
+      reassemble(StreamFormat::OpenAi, Framing::EventStream, CHAT_COMPLETION_STREAM).unwrap();
+    assert_eq!(response.object, "chat.completion");
+    assert_eq!(response.id, "1");
+    assert_eq!(response.model.as_deref(), Some("gpt-4o"));
+    assert_eq!(response.choices.len(), 2);
+    assert_eq!(response.choices[0].message.role, "assistant");
+    assert_eq!(response.choices[0].message.content, "Hello");
+    assert_eq!(response.choices[0].finish_reason, "stop");
+    assert_eq!(response.choices[1].message.content, "Other");
+    assert_eq!(response.usage.unwrap().total_tokens, 5);
+  }
+
+  #[test]
+  fn reassemble_ndjson_stream() {
+    let body = concat!(
+      r#"{"id":"1","object":"chat.completion.chunk","created":0,"#,
+      r#""choices":[{"index":0,"delta":{"content":"a"}}]}"#,
+      "\n",
+      r#"{"id":"1","object":"chat.completion.chunk","created":0,"#,
+      r#""choices":[{"index":0,"delta":{"content":"b"}}]}"#,
+      "\n",
+    );
+    let response = reassemble(StreamFormat::OpenAi, Framing::Ndjson, body).unwrap();
+    assert_eq!(response.choices[0].message.content, "ab");
+  }
+
+  #[test]
+  fn reassemble_ignores_choices_beyond_the_limit() {
+    let body = format!(
+      r#"data: {{"id":"1","object":"chat.completion.chunk","created":0,"choices":[{{"index":{},"#,
+      MAX_CHOICES
+    ) + r#""delta":{"content":"a"}}]}"#;
+    let response = reassemble(StreamFormat::OpenAi, Framing::EventStream, &body).unwrap();
+    assert!(response.choices.is_empty());
+  }
+
+  #[test]
+  fn reassemble_fails_on_invalid_chunks() {
+    assert!(reassemble(StreamFormat::OpenAi, Framing::EventStream, "data: {\n").is_err());
+  }
+
+  #[test]
+  fn reassemble_anthropic_stream() {
+    let response =
+      reassemble(StreamFormat::Anthropic, Framing::EventStream, ANTHROPIC_STREAM).unwrap();
+    assert_eq!(response.id, "msg_1");
+    assert_eq!(response.model.as_deref(), Some("claude"));
+    assert_eq!(response.choices[0].message.content, "Hi");
+    assert_eq!(response.choices[0].finish_reason, "stop");
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.prompt_tokens, usage.completion_tokens, usage.total_tokens), (4, 6, 10));
+  }
+
+  #[test]
+  fn delta_parser_parses_complete_lines_once() {
+    let mut parser = DeltaParser::new(StreamFormat::OpenAi, Framing::EventStream);
+    let mut deltas = Vec::new();
+    let body = CHAT_COMPLETION_STREAM.as_bytes();
+    // Feeds the body in pieces, splitting lines, as it would be received.
+    for end in [10, 150, 151, body.len()] {
+      parser.parse(&body[..end], |delta| deltas.push(delta.to_owned()));
+    }
+    assert_eq!(deltas, ["Hel", "lo"]);
+  }
+
+  #[test]
+  fn delta_parser_parses_anthropic_deltas() {
+    let mut parser = DeltaParser::new(StreamFormat::Anthropic, Framing::EventStream);
+    let mut deltas = Vec::new();
+    parser.parse(ANTHROPIC_STREAM.as_bytes(), |delta| deltas.push(delta.to_owned()));
+    assert_eq!(deltas, ["Hi"]);
+  }
+}
@@ -0,0 +1,142 @@
+use std::{future::Future, pin::Pin};
+
+#[cfg(feature = "test-util")]
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+  time::Duration,
+};
+
+use hyper::{client::connect::Connect, Body, Request, Response};
+#[cfg(feature = "test-util")]
+use hyper::{header::CONTENT_TYPE, StatusCode};
+
+pub type ResponseFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, String>> + Send>>;
+
+/// Sends HTTP requests to the API. Implemented by the hyper client and, for tests, by
+/// [`ScriptedTransport`].
+pub trait ChatTransport: Send + Sync {
+  fn send(&self, request: Request<Body>) -> ResponseFuture;
+}
+
+impl<C> ChatTransport for hyper::Client<C>
+where
+  C: Connect + Clone + Send + Sync + 'static,
+{
+  fn send(&self, request: Request<Body>) -> ResponseFuture {
+    let response = self.request(request);
+    Box::pin(async move { response.await.map_err(|e| e.to_string()) })
+  }
+}
+
+#[cfg(feature = "test-util")]
+struct ScriptedResponse {
+  delay: Duration,
+  status: StatusCode,
+  content_type: &'static str,
+  body: String,
+}
+
+#[cfg(feature = "test-util")]
+#[derive(Default)]
+struct Script {
+  responses: VecDeque<ScriptedResponse>,
+  requests: Vec<String>,
+}
+
+/// In-memory transport answering requests from a queue of scripted responses, in order, so that
+/// the element can be tested without network access. Requests received when the queue is empty
+/// fail as if the connection failed.
+#[cfg(feature = "test-util")]
+#[derive(Clone, Default)]
+pub struct ScriptedTransport {
+  script: Arc<Mutex<Script>>,
+}
+
+#[cfg(feature = "test-util")]
+impl ScriptedTransport {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Queues a JSON response with the given status.
+  pub fn push_response(&self, status: u16, body: impl Into<String>) -> &Self {
+    self.push_delayed_response(Duration::ZERO, status, body)
+  }
+
+  /// Queues a JSON response which is only returned after `delay`.
+  pub fn push_delayed_response(
+    &self,
+    delay: Duration,
+    status: u16,
+    body: impl Into<String>,
+  ) -> &Self {
+    self.push(delay, status, "application/json", body.into())
+  }
+
+  /// Queues a server-sent events response, as returned for streaming requests.
+  pub fn push_event_stream(&self, body: impl Into<String>) -> &Self {
+    self.push(Duration::ZERO, 200, "text/event-stream", body.into())
+  }
+
+  /// Queues a successful chat completion whose only choice has the given content.
+  pub fn push_completion(&self, content: &str) -> &Self {
+    let body = serde_json::json!({
+      "id": "chatcmpl-scripted",
+      "object": "chat.completion",
+      "created": 0,
+      "choices": [{
+        "index": 0,
+        "message": { "role": "assistant", "content": content },
+        "finish_reason": "stop",
+      }],
+    });
+    self.push_response(200, body.to_string())
+  }
+
+  /// Returns the bodies of the requests received so far.
+  pub fn requests(&self) -> Vec<String> {
+    self.script.lock().unwrap().requests.clone()
+  }
+
+  /// Returns the number of scripted responses not returned yet.
+  pub fn remaining(&self) -> usize {
+    self.script.lock().unwrap().responses.len()
+  }
+
+  fn push(&self, delay: Duration, status: u16, content_type: &'static str, body: String) -> &Self {
+    self.script.lock().unwrap().responses.push_back(ScriptedResponse {
+      delay,
+      status: StatusCode::from_u16(status).unwrap(),
+      content_type,
+      body,
+    });
+    self
+  }
+}
+
+#[cfg(feature = "test-util")]
+impl ChatTransport for ScriptedTransport {
+  fn send(&self, request: Request<Body>) -> ResponseFuture {
+    let script = self.script.clone();
+    Box::pin(async move {
+      let body = hyper::body::to_bytes(request.into_body())
+        .await
+        .map_err(|e| e.to_string())?;
+      let response = {
+        let mut script = script.lock().unwrap();
+        script.requests.push(String::from_utf8_lossy(&body).into_owned());
+        script.responses.pop_front()
+      };
+      let response = response.ok_or("no scripted response left")?;
+      tokio::time::sleep(response.delay).await;
+      Ok(
+        Response::builder()
+          .status(response.status)
+          .header(CONTENT_TYPE, response.content_type)
+          .body(response.body.into())
+          .unwrap(),
+      )
+    })
+  }
+}
@@ -1,6 +1,6 @@
 use gstreamer::glib;
 
-mod filter;
+pub mod filter;
 
 fn plugin_init(plugin: &gstreamer::Plugin) -> Result<(), glib::BoolError> {
   filter::register(plugin)?;
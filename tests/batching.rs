@@ -0,0 +1,37 @@
+use std::sync::Once;
+
+use gstreamer::{prelude::*, Buffer};
+use gstreamer_check::Harness;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    gstreamer::init().unwrap();
+    gstopenaichat::plugin_register_static().unwrap();
+  });
+}
+
+#[test]
+fn batched_input_is_joined_with_separator() {
+  init();
+  let mut h = Harness::new("openaichat");
+  {
+    let element = h.element().unwrap();
+    element.set_property("dry-run", true);
+    element.set_property("batch-size", 2u32);
+    element.set_property("join-separator", " | ");
+  }
+  h.set_src_caps_str("text/x-raw, format=utf8");
+
+  h.push(Buffer::from_slice("hello")).unwrap();
+  assert!(h.try_pull().is_none());
+  h.push(Buffer::from_slice("world")).unwrap();
+
+  let output = h.pull().unwrap();
+  let output = output.map_readable().unwrap();
+  let request: serde_json::Value = serde_json::from_slice(output.as_slice()).unwrap();
+  let messages = request["messages"].as_array().unwrap();
+  let last_message = messages.last().unwrap();
+  assert_eq!(last_message["role"], "user");
+  assert_eq!(last_message["content"], "hello | world");
+}
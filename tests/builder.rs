@@ -0,0 +1,23 @@
+use gstopenaichat::filter::OpenaiChatFilter;
+use gstreamer::prelude::ObjectExt;
+
+#[test]
+fn builder_sets_properties() {
+  gstreamer::init().unwrap();
+
+  let filter = OpenaiChatFilter::builder()
+    .model("gpt-4")
+    .system_prompt("You are a helpful assistant.")
+    .temperature(0.5)
+    .output_role_prefix("assistant: ")
+    .build();
+
+  assert_eq!(filter.property::<String>("model"), "gpt-4");
+  assert_eq!(
+    filter.property::<String>("system-prompt"),
+    "You are a helpful assistant."
+  );
+  assert_eq!(filter.property::<f64>("temperature"), 0.5);
+  assert_eq!(filter.property::<String>("output-role-prefix"), "assistant: ");
+  assert_eq!(filter.property::<Option<String>>("token-file"), None);
+}
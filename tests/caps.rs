@@ -0,0 +1,70 @@
+use std::{str::FromStr, sync::Once};
+
+use gstreamer::{prelude::*, Caps};
+use gstreamer_check::Harness;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    gstreamer::init().unwrap();
+    gstopenaichat::plugin_register_static().unwrap();
+  });
+}
+
+fn query_caps(h: &Harness, pad_name: &str, filter: Option<&str>) -> Caps {
+  let pad = h.element().unwrap().static_pad(pad_name).unwrap();
+  let filter = filter.map(|filter| Caps::from_str(filter).unwrap());
+  pad.query_caps(filter.as_ref())
+}
+
+#[test]
+fn sink_caps_without_filter() {
+  init();
+  let h = Harness::new("openaichat");
+  assert_eq!(
+    query_caps(&h, "sink", None),
+    Caps::from_str("text/x-raw, format=utf8").unwrap()
+  );
+}
+
+#[test]
+fn sink_caps_with_filter() {
+  init();
+  let h = Harness::new("openaichat");
+  assert_eq!(
+    query_caps(&h, "sink", Some("text/x-raw")),
+    Caps::from_str("text/x-raw, format=utf8").unwrap()
+  );
+  assert!(query_caps(&h, "sink", Some("text/x-raw, format=pango-markup")).is_empty());
+}
+
+#[test]
+fn sink_caps_follow_downstream_restrictions() {
+  init();
+  let mut h = Harness::new("openaichat");
+  h.set_sink_caps_str("text/x-raw, format=pango-markup");
+  assert!(query_caps(&h, "sink", None).is_empty());
+}
+
+#[test]
+fn src_caps_without_filter() {
+  init();
+  let mut h = Harness::new("openaichat");
+  h.set_src_caps_str("text/x-raw, format=utf8");
+  assert_eq!(
+    query_caps(&h, "src", None),
+    Caps::from_str("text/x-raw, format=utf8").unwrap()
+  );
+}
+
+#[test]
+fn src_caps_with_filter() {
+  init();
+  let mut h = Harness::new("openaichat");
+  h.set_src_caps_str("text/x-raw, format=utf8");
+  assert_eq!(
+    query_caps(&h, "src", Some("text/x-raw")),
+    Caps::from_str("text/x-raw, format=utf8").unwrap()
+  );
+  assert!(query_caps(&h, "src", Some("application/json")).is_empty());
+}
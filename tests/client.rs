@@ -0,0 +1,79 @@
+use std::{
+  io::{BufRead, BufReader, Read, Write},
+  net::TcpListener,
+  sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+  },
+  thread,
+};
+
+use gstopenaichat::filter::client::{
+  CompletionClient, CompletionSettings, OpenaiChatCompletionMessage,
+};
+
+/// Serves `responses` chat completions over plain HTTP with keep-alive, counting the connections.
+fn serve(responses: usize) -> (String, Arc<AtomicUsize>) {
+  let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+  let endpoint = format!("http://{}/v1/chat/completions", listener.local_addr().unwrap());
+  let connections = Arc::new(AtomicUsize::new(0));
+  thread::spawn({
+    let connections = connections.clone();
+    move || {
+      let mut served = 0;
+      for stream in listener.incoming() {
+        connections.fetch_add(1, Ordering::SeqCst);
+        let mut reader = BufReader::new(stream.unwrap());
+        while served < responses {
+          let mut content_length = 0;
+          loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+              break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+              content_length = value.trim().parse().unwrap();
+            }
+            if line == "\r\n" {
+              break;
+            }
+          }
+          let mut body = vec![0; content_length];
+          reader.read_exact(&mut body).unwrap();
+          let body = r#"{"id":"1","object":"chat.completion","created":0,"choices":[{"index":0,
+            "message":{"role":"assistant","content":"hello"},"finish_reason":"stop"}]}"#;
+          write!(
+            reader.get_mut(),
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+          )
+          .unwrap();
+          served += 1;
+        }
+      }
+    }
+  });
+  (endpoint, connections)
+}
+
+#[test]
+fn completion_client_reuses_its_connection() {
+  let (endpoint, connections) = serve(2);
+  let client = CompletionClient::new(CompletionSettings {
+    endpoint,
+    allow_http: true,
+    ..Default::default()
+  });
+  let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build().unwrap();
+  for _ in 0..2 {
+    let messages = vec![OpenaiChatCompletionMessage {
+      role: "user".into(),
+      content: "hi".into(),
+      ..Default::default()
+    }];
+    let response = runtime.block_on(client.complete(messages)).unwrap();
+    assert_eq!(response.choices[0].message.content, "hello");
+  }
+  assert_eq!(connections.load(Ordering::SeqCst), 1);
+}
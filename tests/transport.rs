@@ -0,0 +1,657 @@
+#![cfg(feature = "test-util")]
+
+use std::{
+  sync::{Arc, Mutex, Once},
+  thread,
+  time::Duration,
+};
+
+use gstopenaichat::filter::{
+  FinishReason, OpenAiRequestParams, OpenAiRequestParamsMeta, OpenaiChatFilter, ScriptedTransport,
+};
+use gstreamer::{glib, prelude::*, Buffer};
+use gstreamer_check::Harness;
+
+fn init() {
+  static INIT: Once = Once::new();
+  INIT.call_once(|| {
+    gstreamer::init().unwrap();
+    gstopenaichat::plugin_register_static().unwrap();
+  });
+}
+
+fn harness(transport: &ScriptedTransport) -> Harness {
+  let mut h = Harness::new("openaichat");
+  {
+    let element = h.element().unwrap();
+    element.set_property("api-keys", gstreamer::Array::new(["test"]));
+    element
+      .downcast_ref::<OpenaiChatFilter>()
+      .unwrap()
+      .set_transport(transport.clone());
+  }
+  h.set_src_caps_str("text/x-raw, format=utf8");
+  h
+}
+
+/// Like `harness`, with `properties` set before the element starts, for the ones that cannot be
+/// changed while it is running.
+fn harness_with(transport: &ScriptedTransport, properties: &[(&str, glib::Value)]) -> Harness {
+  let element = gstreamer::ElementFactory::make("openaichat")
+    .property("api-keys", gstreamer::Array::new(["test"]))
+    .build()
+    .unwrap();
+  for (name, value) in properties {
+    element.set_property_from_value(name, value);
+  }
+  element
+    .downcast_ref::<OpenaiChatFilter>()
+    .unwrap()
+    .set_transport(transport.clone());
+  let mut h = Harness::with_element(&element, Some("sink"), Some("src"));
+  h.set_src_caps_str("text/x-raw, format=utf8");
+  h
+}
+
+fn pull_text(h: &mut Harness) -> String {
+  let buffer = h.pull().unwrap();
+  let map = buffer.map_readable().unwrap();
+  String::from_utf8(map.as_slice().to_vec()).unwrap()
+}
+
+fn request(transport: &ScriptedTransport, index: usize) -> serde_json::Value {
+  serde_json::from_str(&transport.requests()[index]).unwrap()
+}
+
+#[test]
+fn responses_are_pushed_and_added_to_the_history() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "first\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "second\n");
+
+  let messages = request(&transport, 1)["messages"].as_array().unwrap().clone();
+  let contents: Vec<_> = messages
+    .iter()
+    .filter(|message| message["role"] != "system")
+    .map(|message| message["content"].as_str().unwrap().to_owned())
+    .collect();
+  assert_eq!(contents, ["one", "first", "two"]);
+}
+
+#[test]
+fn src_caps_follow_the_output_format() {
+  init();
+  for (properties, expected) in [
+    (vec![], "text/x-raw, format=(string)utf8"),
+    (vec![("output-envelope", true.to_value())], "application/json"),
+  ] {
+    let transport = ScriptedTransport::new();
+    transport.push_completion("hello");
+    let mut h = harness_with(&transport, &properties);
+
+    h.push(Buffer::from_slice("hi")).unwrap();
+    h.pull().unwrap();
+    let caps = h.sinkpad().unwrap().current_caps().unwrap();
+    assert_eq!(caps, expected.parse::<gstreamer::Caps>().unwrap());
+  }
+}
+
+#[test]
+fn finish_reason_is_tagged() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness(&transport);
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  let event = h
+    .sinkpad()
+    .unwrap()
+    .sticky_event::<gstreamer::event::Tag>(0)
+    .unwrap();
+  let finish_reason = event.tag().get::<FinishReason>().unwrap();
+  assert_eq!(finish_reason.get(), "stop");
+}
+
+#[test]
+fn preloaded_history_starts_the_conversation() {
+  init();
+  let path = std::env::temp_dir().join("openaichat-preload-history.json");
+  let history = r#"[{"role":"user","content":"ping"},{"role":"assistant","content":"pong"}]"#;
+  std::fs::write(&path, history).unwrap();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness_with(
+    &transport,
+    &[("preload-history-file", path.to_str().unwrap().to_value())],
+  );
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  let messages = request(&transport, 0)["messages"].as_array().unwrap().clone();
+  let contents: Vec<_> = messages
+    .iter()
+    .filter(|message| message["role"] != "system")
+    .map(|message| message["content"].as_str().unwrap().to_owned())
+    .collect();
+  assert_eq!(contents, ["ping", "pong", "hi"]);
+  assert_eq!(std::fs::read_to_string(&path).unwrap(), history);
+}
+
+#[test]
+fn greeting_is_pushed_and_recorded() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness_with(
+    &transport,
+    &[
+      ("greeting", "Welcome!".to_value()),
+      ("record-greeting", true.to_value()),
+    ],
+  );
+
+  assert_eq!(pull_text(&mut h), "Welcome!\n");
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  let messages = request(&transport, 0)["messages"].as_array().unwrap().clone();
+  let roles: Vec<_> = messages.iter().map(|message| message["role"].clone()).collect();
+  assert_eq!(roles, ["system", "assistant", "user"]);
+  assert_eq!(messages[1]["content"], "Welcome!");
+}
+
+#[test]
+fn max_history_keeps_the_system_prompt() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("max-history", 2u32);
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "first\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "second\n");
+
+  let messages = request(&transport, 1)["messages"].as_array().unwrap().clone();
+  let roles: Vec<_> = messages.iter().map(|message| message["role"].clone()).collect();
+  assert_eq!(roles, ["system", "assistant", "user"]);
+}
+
+#[test]
+fn max_history_keeps_the_few_shot_messages() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+  let few_shot = r#"[{"role":"user","content":"ping"},{"role":"assistant","content":"pong"}]"#;
+  h.element().unwrap().set_property("few-shot", few_shot);
+  h.element().unwrap().set_property("max-history", 2u32);
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "first\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "second\n");
+
+  let messages = request(&transport, 1)["messages"].as_array().unwrap().clone();
+  let contents: Vec<_> = messages
+    .iter()
+    .filter(|message| message["role"] != "system")
+    .map(|message| message["content"].as_str().unwrap().to_owned())
+    .collect();
+  assert_eq!(contents, ["ping", "pong", "first", "two"]);
+}
+
+#[test]
+fn least_recently_used_conversation_is_evicted() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+  let element = h.element().unwrap();
+  element.set_property("max-conversations", 1u32);
+  let evicted = Arc::new(Mutex::new(Vec::new()));
+  element.connect("conversation-evicted", false, {
+    let evicted = evicted.clone();
+    move |args| {
+      evicted.lock().unwrap().push(args[1].get::<String>().unwrap());
+      None
+    }
+  });
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "first\n");
+  let conversation = gstreamer::Structure::builder("GstOpenAiConversation")
+    .field("id", "user-42")
+    .build();
+  assert!(h.push_event(gstreamer::event::CustomDownstream::new(conversation)));
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "second\n");
+
+  assert_eq!(*evicted.lock().unwrap(), [""]);
+  assert_eq!(element.property::<u32>("active-conversations"), 1);
+  assert_eq!(element.property::<u64>("evicted-conversations"), 1);
+}
+
+#[test]
+fn request_params_meta_overrides_properties() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("seed", 1i64);
+
+  let mut buffer = Buffer::from_slice("one");
+  OpenAiRequestParamsMeta::add(
+    buffer.get_mut().unwrap(),
+    OpenAiRequestParams {
+      seed: Some(2),
+      max_tokens: Some(10),
+      ..Default::default()
+    },
+  );
+  h.push(buffer).unwrap();
+  assert_eq!(pull_text(&mut h), "first\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "second\n");
+
+  assert_eq!(request(&transport, 0)["seed"], 2);
+  assert_eq!(request(&transport, 0)["max_tokens"], 10);
+  assert_eq!(request(&transport, 1)["seed"], 1);
+  assert!(request(&transport, 1).get("max_tokens").is_none());
+}
+
+#[test]
+fn higher_priority_request_preempts_the_request_in_flight() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport
+    .push_delayed_response(Duration::from_secs(60), 200, "{}")
+    .push_completion("interactive")
+    .push_completion("background");
+  let mut h = harness(&transport);
+
+  h.push(Buffer::from_slice("bulk")).unwrap();
+  while transport.requests().is_empty() {
+    thread::sleep(Duration::from_millis(10));
+  }
+  let mut buffer = Buffer::from_slice("chat");
+  OpenAiRequestParamsMeta::add(
+    buffer.get_mut().unwrap(),
+    OpenAiRequestParams {
+      priority: Some(1),
+      ..Default::default()
+    },
+  );
+  h.push(buffer).unwrap();
+  assert_eq!(pull_text(&mut h), "interactive\n");
+  assert_eq!(pull_text(&mut h), "background\n");
+  assert_eq!(transport.requests().len(), 3);
+}
+
+#[test]
+fn rejected_stream_falls_back_to_a_complete_response() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport
+    .push_response(400, r#"{"error":{"message":"stream is not supported"}}"#)
+    .push_completion("hello");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("stream", true);
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(request(&transport, 0)["stream"], true);
+  assert!(request(&transport, 1).get("stream").is_none());
+}
+
+#[test]
+fn response_within_flush_after_ms_is_pushed_whole() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_event_stream(concat!(
+    r#"data: {"id":"1","created":0,"choices":[{"index":0,"delta":{"content":"hel"}}]}"#,
+    "\n\n",
+    r#"data: {"id":"1","created":0,"choices":[{"index":0,"delta":{"content":"lo"}}]}"#,
+    "\n\ndata: [DONE]\n\n",
+  ));
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("flush-after-ms", 60_000u32);
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(request(&transport, 0)["stream"], true);
+}
+
+#[test]
+fn transform_response_replaces_the_content() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("first").push_completion("second");
+  let mut h = harness(&transport);
+  let element = h.element().unwrap();
+  element.connect("transform-response", false, |args| {
+    let content = args[1].get::<String>().unwrap();
+    Some(Some(content.to_uppercase()).to_value())
+  });
+  element.connect("transform-response", false, |_| Some(None::<String>.to_value()));
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "FIRST\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "SECOND\n");
+  assert_eq!(request(&transport, 1)["messages"][2]["content"], "FIRST");
+}
+
+#[test]
+fn duplicate_responses_are_not_pushed() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport
+    .push_completion("same")
+    .push_completion("same")
+    .push_completion("other");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("dedupe-responses", true);
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "same\n");
+  h.push(Buffer::from_slice("two")).unwrap();
+  h.push(Buffer::from_slice("three")).unwrap();
+  assert_eq!(pull_text(&mut h), "other\n");
+}
+
+#[test]
+fn queued_output_is_pushed_before_eos() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness_with(&transport, &[("queue-output", true.to_value())]);
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert!(h.push_event(gstreamer::event::Eos::new()));
+  assert_eq!(pull_text(&mut h), "hello\n");
+}
+
+#[test]
+fn prompt_prefix_and_suffix_wrap_the_input() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("prompt-prefix", "User question: ");
+  h.element().unwrap().set_property("prompt-suffix", "?");
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(request(&transport, 0)["messages"][1]["content"], "User question: hi?");
+}
+
+#[test]
+fn placeholders_in_the_input_are_not_expanded() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("user-template", "Turn {turn}: {input}");
+
+  h.push(Buffer::from_slice("say {turn}")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(request(&transport, 0)["messages"][1]["content"], "Turn 1: say {turn}");
+}
+
+#[test]
+fn unlinked_src_fails_only_with_require_src() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let element = gstreamer::ElementFactory::make("openaichat")
+    .property("api-keys", gstreamer::Array::new(["test"]))
+    .build()
+    .unwrap();
+  element
+    .downcast_ref::<OpenaiChatFilter>()
+    .unwrap()
+    .set_transport(transport.clone());
+  let mut h = Harness::with_element(&element, Some("sink"), None);
+  h.set_src_caps_str("text/x-raw, format=utf8");
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  element.set_property("require-src", true);
+  assert_eq!(h.push(Buffer::from_slice("two")), Err(gstreamer::FlowError::NotLinked));
+}
+
+#[test]
+fn reasoning_is_kept_out_of_the_answer() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_response(
+    200,
+    r#"{"id":"1","object":"chat.completion","created":0,
+      "choices":[{"index":0,"finish_reason":"stop",
+      "message":{"role":"assistant","content":"4","reasoning_content":"2 + 2 = 4"}}]}"#,
+  );
+  let element = gstreamer::ElementFactory::make("openaichat")
+    .property("api-keys", gstreamer::Array::new(["test"]))
+    .property_from_str("reasoning-output", "signal")
+    .build()
+    .unwrap();
+  element
+    .downcast_ref::<OpenaiChatFilter>()
+    .unwrap()
+    .set_transport(transport.clone());
+  let reasoning = Arc::new(Mutex::new(None));
+  element.connect("response-received", false, {
+    let reasoning = reasoning.clone();
+    move |args| {
+      let info = args[1].get::<gstreamer::Structure>().unwrap();
+      *reasoning.lock().unwrap() = info.get::<String>("reasoning").ok();
+      None
+    }
+  });
+  let mut h = Harness::with_element(&element, Some("sink"), Some("src"));
+  h.set_src_caps_str("text/x-raw, format=utf8");
+
+  h.push(Buffer::from_slice("2 + 2?")).unwrap();
+  assert_eq!(pull_text(&mut h), "4\n");
+  assert_eq!(reasoning.lock().unwrap().as_deref(), Some("2 + 2 = 4"));
+}
+
+#[test]
+fn request_can_be_form_encoded() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property_from_str("request-content-type", "form");
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  let body = transport.requests()[0].clone();
+  let fields: Vec<_> = body.split('&').collect();
+  assert!(fields.contains(&"model=gpt-3.5-turbo"));
+  assert!(fields.iter().any(|field| field.starts_with("messages=%5B%7B%22")));
+}
+
+#[test]
+fn anthropic_requests_use_the_messages_api() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_response(
+    200,
+    r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude",
+      "content":[{"type":"text","text":"hello"}],"stop_reason":"end_turn",
+      "usage":{"input_tokens":10,"output_tokens":1}}"#,
+  );
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property_from_str("provider", "anthropic");
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  let request = request(&transport, 0);
+  assert!(request["system"].is_string());
+  assert!(request["max_tokens"].is_u64());
+  let roles: Vec<_> = request["messages"]
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|message| message["role"].clone())
+    .collect();
+  assert_eq!(roles, ["user"]);
+  assert_eq!(h.element().unwrap().property::<u64>("last-prompt-tokens"), 10);
+}
+
+#[test]
+fn empty_response_is_retried() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("").push_completion("hello");
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property("retry-on-empty", true);
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(transport.requests().len(), 2);
+}
+
+#[test]
+fn choices_missing_from_the_moderation_response_are_blocked() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello").push_response(200, r#"{"results":[]}"#);
+  let mut h = harness_with(
+    &transport,
+    &[("moderate-output", true.to_value()), ("moderation-replacement", "[blocked]".to_value())],
+  );
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "[blocked]\n");
+}
+
+#[test]
+fn moderation_response_is_limited_by_max_response_bytes() {
+  init();
+  let transport = ScriptedTransport::new();
+  let padding = "x".repeat(1000);
+  let moderation = format!(r#"{{"results":[{{"flagged":false}}],"padding":"{}"}}"#, padding);
+  transport.push_completion("hello").push_response(200, moderation);
+  let mut h = harness_with(
+    &transport,
+    &[
+      ("moderate-output", true.to_value()),
+      ("moderation-replacement", "[blocked]".to_value()),
+      ("max-response-bytes", 500u32.to_value()),
+    ],
+  );
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "[blocked]\n");
+}
+
+#[test]
+fn http_error_pushes_nothing() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport
+    .push_response(500, r#"{"error":{"message":"server error"}}"#)
+    .push_completion("recovered");
+  let mut h = harness(&transport);
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "recovered\n");
+  assert_eq!(transport.remaining(), 0);
+}
+
+#[test]
+fn response_without_choices_is_an_error() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport
+    .push_response(200, r#"{"id":"1","object":"chat.completion","created":0,"choices":[]}"#)
+    .push_completion("recovered");
+  let mut h = harness(&transport);
+  let bus = gstreamer::Bus::new();
+  h.element().unwrap().set_bus(Some(&bus));
+  h.element().unwrap().set_property_from_str("on-error", "error");
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  let message = bus
+    .timed_pop_filtered(gstreamer::ClockTime::from_seconds(5), &[gstreamer::MessageType::Error])
+    .unwrap();
+  if let gstreamer::MessageView::Error(error) = message.view() {
+    let debug = error.debug().unwrap_or_default();
+    assert!(debug.contains("has no choices"), "{}", debug);
+  }
+  h.push(Buffer::from_slice("two")).unwrap();
+  assert_eq!(pull_text(&mut h), "recovered\n");
+}
+
+#[test]
+fn http_error_passes_input_through() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_response(500, r#"{"error":{"message":"server error"}}"#);
+  let mut h = harness(&transport);
+  h.element().unwrap().set_property_from_str("on-error", "passthrough");
+
+  h.push(Buffer::from_slice("one")).unwrap();
+  assert_eq!(pull_text(&mut h), "one\n");
+}
+
+#[test]
+fn ping_uses_api_keys() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("pong");
+  let h = harness(&transport);
+
+  assert!(h.element().unwrap().emit_by_name::<bool>("ping", &[]));
+  assert_eq!(transport.remaining(), 0);
+}
+
+#[test]
+fn ping_fails_on_a_runtime_thread() {
+  init();
+  let transport = ScriptedTransport::new();
+  transport.push_completion("hello");
+  let mut h = harness(&transport);
+  let pinged = Arc::new(Mutex::new(None));
+  h.element().unwrap().connect("response-received", false, {
+    let pinged = pinged.clone();
+    move |args| {
+      let element = args[0].get::<gstreamer::Element>().unwrap();
+      *pinged.lock().unwrap() = Some(element.emit_by_name::<bool>("ping", &[]));
+      None
+    }
+  });
+
+  h.push(Buffer::from_slice("hi")).unwrap();
+  assert_eq!(pull_text(&mut h), "hello\n");
+  assert_eq!(*pinged.lock().unwrap(), Some(false));
+}
+
+#[test]
+fn optional_pads_are_only_added_when_enabled() {
+  init();
+  let h = harness(&ScriptedTransport::new());
+  let pads: Vec<_> = h.element().unwrap().pads().iter().map(|pad| pad.name()).collect();
+  assert_eq!(pads, ["sink", "src"]);
+
+  let h = harness_with(&ScriptedTransport::new(), &[("code-pad", true.to_value())]);
+  assert!(h.element().unwrap().static_pad("code").is_some());
+
+  let element = gstreamer::ElementFactory::make("openaichat")
+    .property_from_str("modalities", "text+audio")
+    .build()
+    .unwrap();
+  let _h = Harness::with_element(&element, Some("sink"), Some("src"));
+  assert!(element.static_pad("audio").is_some());
+}